@@ -0,0 +1,41 @@
+//! Derives the build id the firmware reports in the Hello handshake from its sources,
+//! so two images built from different code report different ids.
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// the firmware and the protocol it speaks
+const SOURCES: [&str; 2] = ["src", "../lib/src"];
+
+fn collect(dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else { return };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect(&path, files);
+        } else {
+            files.push(path);
+        }
+    }
+}
+
+// fnv-1a
+fn hash(hash: u32, bytes: &[u8]) -> u32 {
+    bytes.iter().fold(hash, |hash, &byte| (hash ^ byte as u32).wrapping_mul(0x01000193))
+}
+
+fn main() {
+    let mut files = Vec::new();
+    for dir in SOURCES {
+        // a directory is scanned for changes as a whole
+        println!("cargo:rerun-if-changed={dir}");
+        collect(Path::new(dir), &mut files);
+    }
+    // the same sources give the same id whatever order the file system lists them in
+    files.sort();
+    let mut id = 0x811c9dc5;
+    for file in files {
+        id = hash(id, file.to_string_lossy().as_bytes());
+        id = hash(id, &fs::read(&file).unwrap_or_default());
+    }
+    println!("cargo:rustc-env=BUILD_ID={id:08x}");
+}
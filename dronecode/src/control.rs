@@ -1,75 +1,136 @@
+use core::mem::MaybeUninit;
+use core::ptr::addr_of_mut;
 use fixed::types::I22F10;
-use tudelft_quadrupel::barometer::read_pressure;
 use crate::control::drone::Drone;
-use tudelft_quadrupel::battery::read_battery;
-use tudelft_quadrupel::led::Led::{Blue,  Red};
-use tudelft_quadrupel::motor::get_motors;
-use tudelft_quadrupel::mpu::read_raw;
-use tudelft_quadrupel::time::{set_tick_frequency, wait_for_next_tick, Instant};
-use tudelft_quadrupel::uart::{send_bytes,receive_bytes};
-use share_lib::{ Message, Command, serialize_message, Mode};
+use crate::hal::{DroneHal, Led};
+use share_lib::{FrameDecoder, Command, serialize_message, Mode};
 use share_lib::Command::Datalog;
 use crate::control::datalog::datalog;
-use crate::control::info::{send_configure_joystick_vals, send_motor_vals};
+use crate::control::info::{send_configure_joystick_vals, send_telemetry};
 
 pub mod drone;
-mod utils;
+pub mod mixer;
 mod datalog;
+mod config;
 pub mod yaw_pitch_roll;
-mod pid;
+pub mod pid;
 mod info;
+mod params;
 mod fsm;
-const TICK_FREQ: u64 = 100; // Tick frequency in Hz
-///control loop when the drone is running
-pub fn control_loop() -> ! {
-    set_tick_frequency(TICK_FREQ);
-    let mut last_keepalive = Instant::now();
-    let mut real_buffer = [0;256];
-    let mut keepalive_count: u32 = 0;
-    let start_point = Instant::now();
-    let mut start_flag = false;
-    let mut len = 0;
-    let mut drone = Drone::new();
-    let mut count = 0;
-    for i in 0.. {
+pub const TICK_FREQ: u64 = 100; // Tick frequency in Hz
+const TICK_US: u32 = (1_000_000 / TICK_FREQ) as u32; // Tick period in us
+
+//answer an acknowledged command with its sequence number
+fn send_ack(hal: &mut impl DroneHal, (seq, accepted): (u8, bool)) {
+    if accepted {
+        hal.send_bytes(&serialize_message(Command::Ack { seq }));
+    } else {
+        hal.send_bytes(&serialize_message(Command::Nack { seq }));
+    }
+}
+
+//answers to the last acknowledged commands. The runner keeps several commands waiting and
+//retransmits each of them, so a retransmission can arrive after a newer command
+const ACK_HISTORY: usize = 8;
+
+struct AckHistory {
+    answers: [Option<(u8, bool)>; ACK_HISTORY],
+    next: usize, // slot the next answer goes to
+    newest: Option<u8>, // sequence number of the newest command carried out
+}
+
+impl AckHistory {
+    fn new() -> Self {
+        AckHistory { answers: [None; ACK_HISTORY], next: 0, newest: None }
+    }
+
+    //the answer a retransmission gets again. A sequence number at or behind the newest one that was
+    //never answered belongs to a command that was overtaken, carrying it out now could undo a newer one
+    fn answered(&self, seq: u8) -> Option<bool> {
+        if let Some((_, accepted)) = self.answers.iter().flatten().find(|(answered, _)| *answered == seq) {
+            return Some(*accepted);
+        }
+        match self.newest {
+            Some(newest) if seq.wrapping_sub(newest) as i8 <= 0 => Some(false),
+            _ => None,
+        }
+    }
+
+    fn record(&mut self, seq: u8, accepted: bool) {
+        self.answers[self.next] = Some((seq, accepted));
+        self.next = (self.next + 1) % ACK_HISTORY;
+        self.newest = Some(seq);
+    }
+}
+
+//state of the control loop that outlives a single tick
+pub struct Controller<H: DroneHal> {
+    pub drone: Drone<H>,
+    last_keepalive: u64,
+    keepalive_count: u32,
+    decoder: FrameDecoder,
+    acks: AckHistory,
+}
+
+impl<H: DroneHal> Controller<H> {
+    pub fn new(hal: H) -> Self {
+        let mut controller = MaybeUninit::uninit();
+        Self::init(&mut controller, hal);
+        // SAFETY: init writes every field
+        unsafe { controller.assume_init() }
+    }
+
+    //builds the controller in place, on the board it lives in a static
+    pub fn init(slot: &mut MaybeUninit<Self>, hal: H) -> &mut Self {
+        let controller = slot.as_mut_ptr();
+        // SAFETY: the pointers come from the slot, the drone is built in place and then the other fields are written
+        unsafe {
+            let drone = Drone::init(&mut *addr_of_mut!((*controller).drone).cast(), hal);
+            drone.hal.set_tick_frequency(TICK_FREQ);
+            let now = drone.hal.now_us();
+            addr_of_mut!((*controller).last_keepalive).write(now);
+            addr_of_mut!((*controller).keepalive_count).write(0);
+            addr_of_mut!((*controller).decoder).write(FrameDecoder::new());
+            addr_of_mut!((*controller).acks).write(AckHistory::new());
+            slot.assume_init_mut()
+        }
+    }
+
+    //one iteration of the control loop, the caller waits for the next tick
+    pub fn tick(&mut self) {
+        let drone = &mut self.drone;
+        let i = drone.tick;
         let mut buffer = [0;32];
         // let time1 = Instant::now();
-        let now = Instant::now();
-        let dt_keepalive = now.duration_since(last_keepalive);
+        let now = drone.hal.now_us();
+        let dt_keepalive = now - self.last_keepalive;
         // let dt = now.duration_since(last);
         // last = now;
 
 
-        let t = receive_bytes(&mut buffer);
+        let t = drone.hal.receive_bytes(&mut buffer);
         if t > 0 { // Bytes received from serial communication
-            last_keepalive = now;
-            keepalive_count = 0;
-            let buffer_cpy = buffer.clone();
-            for buf_idx in 0..t {
-                if start_flag{
-                    len = buffer_cpy[buf_idx] as usize;
-                    start_flag = false;
-                }
-                if buffer_cpy[buf_idx] == 0xFE {
-                    count = 0;
-                    start_flag = true;
-                }
-                if count > 255 {
-                    count = 0;
+            self.last_keepalive = now;
+            self.keepalive_count = 0;
+            //frames that fail their checks are only counted in the link statistics
+            for message in self.decoder.feed(&buffer[..t]).into_iter().flatten() {
+                //a runner that connects starts its sequence numbers again
+                if matches!(message.command(), Command::Hello { .. }) {
+                    self.acks = AckHistory::new();
                 }
-                real_buffer[count] = buffer_cpy[buf_idx];
-                count += 1;
-                if buffer_cpy[buf_idx] == 0xFF && count > 2 && (len+5) == count {
-                    match Message::get_message(&real_buffer[0..count]) {
-                        Some(cmd) => {
-                            last_keepalive = now;
-                            drone.process_command(cmd);
-                        },
+                match message.seq() {
+                    None => {
+                        drone.process_command(message.into_command());
+                    }
+                    //a retransmission is only answered again
+                    Some(seq) => match self.acks.answered(seq) {
+                        Some(accepted) => send_ack(&mut drone.hal, (seq, accepted)),
                         None => {
-
+                            let accepted = drone.process_command(message.into_command());
+                            self.acks.record(seq, accepted);
+                            send_ack(&mut drone.hal, (seq, accepted));
                         }
-                    }
-                    count = 0;
+                    },
                 }
             }
         }
@@ -80,63 +141,202 @@ pub fn control_loop() -> ! {
                 let read_val = drone.sensor_ypr;
                 drone.read_raw_sensor();
                 let raw_data = drone.raw_data.current_ypr;
-                let count_point = Instant::now();
-                let motor_val = get_motors();
-                let (_,speed) = read_raw().unwrap();
-                let time = count_point.duration_since(start_point);
-                let time1 = Instant::now();
-                drone.current_point = datalog(Datalog {
+                let count_point = drone.hal.now_us();
+                let motor_val = drone.hal.get_motors();
+                let (_,speed) = drone.hal.read_raw();
+                let time = count_point - drone.boot;
+                let time1 = drone.hal.now_us();
+                drone.current_point = datalog(&mut drone.hal, Datalog {
                     mode:drone.mode,
                     ypr: [read_val.yaw.to_num(), read_val.pitch.to_num(), read_val.roll.to_num()],
                     raw_ypr: [raw_data.yaw.to_num(), raw_data.pitch.to_num(), raw_data.roll.to_num()],
                     motor: motor_val,
-                    time: time.as_millis(),
-                    raw_speed:(speed.y as f32*0.017)
-                },drone.current_point);
-            let time2 = Instant::now();
-            let t1 = time2.duration_since(time1).as_micros();
-            send_bytes(&serialize_message(Command::Time {num:t1}));
+                    time: (time / 1000) as u128,
+                    raw_speed:(speed.y as f32*0.017),
+                    tick: i,
+                },drone.current_point, drone.log_fec);
+            let time2 = drone.hal.now_us();
+            let t1 = (time2 - time1) as u128;
+            drone.hal.send_bytes(&serialize_message(Command::Time {num:t1}));
             //send the configuration info for joystick
-            if i % 9 == 0 {
-                Blue.toggle();
-                send_configure_joystick_vals(&drone);
-            }
-            //send real-time motor value
-            if i % 2 == 0 {
-                Blue.toggle();
-                send_motor_vals();
+            if i.is_multiple_of(9) {
+                drone.hal.led_toggle(Led::Blue);
+                send_configure_joystick_vals(drone);
             }
-            //send real-time height value
-            if i % 6 == 0 {
+            //update the height
+            if i.is_multiple_of(6) {
 
                 // let pr = I22F10::from_num(read_temperature()).to_bits();
                 // drone.pressure = height_clac(drone.pressure);
-                let pr = I22F10::from_num(read_pressure());
+                let pr = I22F10::from_num(drone.hal.read_pressure());
                 // drone.pressure = h_butterworth.filter(pr);
-                drone.height.current_high = drone.height.calibration_p-pr;
-                send_bytes(&serialize_message(Command::Height {num:(drone.height.calibration_p-pr).to_bits()}));
+                drone.height.pressure_update(pr);
             }
+            let accel = drone.vertical_accel();
+            drone.height.height_update(accel);
 
         }
 
-        let dt = dt_keepalive.as_micros();
+        let dt = dt_keepalive as u128;
         drone.operate(dt);
 
-        if i % 5 == 0 && i > 200 { // prevents panic in the first 200 iterations
-            keepalive_count += 1;
-            if keepalive_count >= 20 {
-                drone.process_command(Command::ModeChange { mode: share_lib::Mode::Panic }); // Go into panic mode
-            }
-            let bat = read_battery();
-            if bat < 1050 && bat > 500{
-                drone.process_command(Command::ModeChange { mode: share_lib::Mode::Panic }); // Go into panic mode
+        if i.is_multiple_of(5) {
+            // the mixer makes up for the battery from the start
+            let bat = drone.hal.read_battery();
+            drone.battery = bat;
+            drone.mixer.set_battery(bat);
+            if i > 200 { // prevents panic in the first 200 iterations
+                self.keepalive_count += 1;
+                if self.keepalive_count >= 20 {
+                    drone.process_command(Command::ModeChange { mode: share_lib::Mode::Panic }); // Go into panic mode
+                }
+                if bat < 1050 && bat > 500{
+                    drone.process_command(Command::ModeChange { mode: share_lib::Mode::Panic }); // Go into panic mode
+                }
             }
-            send_bytes(&serialize_message(Command::BatteryCheck { num: bat}));
         }
+        //send the state of this tick as one telemetry frame
+        if drone.telemetry_period != 0 && i.is_multiple_of(drone.telemetry_period as u32) {
+            drone.hal.led_toggle(Led::Blue);
+            send_telemetry(drone);
+        }
+        //answer a running ParamList
+        drone.send_next_param_info();
+        //report how well the link is doing
+        if i.is_multiple_of(100) {
+            drone.hal.send_bytes(&serialize_message(Command::LinkHealth { stats: self.decoder.stats() }));
+        }
+        drone.tick = i.wrapping_add(1);
+    }
+}
+
+///control loop when the drone is running, the controller is too large for the stack of the drone
+pub fn control_loop<H: DroneHal>(controller: &mut Controller<H>) -> ! {
+    loop {
+        controller.tick();
         // wait until the timer interrupt goes off again
         // based on the frequency set above
-        wait_for_next_tick();
+        controller.drone.hal.wait_for_next_tick();
     }
-    unreachable!();
 }
 
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use share_lib::{serialize_message_seq, FrameDecoder};
+    use crate::hal::MockHal;
+    use super::*;
+
+    fn receive(controller: &mut Controller<MockHal>, bytes: &[u8]) -> Vec<Command> {
+        controller.drone.hal.tx.clear();
+        controller.drone.hal.rx.extend(bytes);
+        controller.tick();
+        controller.drone.hal.wait_for_next_tick();
+        let sent = core::mem::take(&mut controller.drone.hal.tx);
+        FrameDecoder::new().feed(&sent).into_iter().filter_map(|frame| frame.ok()).map(|m| m.into_command()).collect()
+    }
+
+    fn answers(sent: &[Command]) -> Vec<Command> {
+        sent.iter().filter(|cmd| matches!(cmd, Command::Ack { .. } | Command::Nack { .. })).cloned().collect()
+    }
+
+    #[test]
+    fn retransmission_does_not_undo_a_newer_mode() {
+        let mut controller = Controller::new(MockHal::new());
+        let manual = Command::ModeChange { mode: Mode::Manual };
+        assert_eq!(answers(&receive(&mut controller, &serialize_message_seq(manual.clone(), 0))), [Command::Ack { seq: 0 }]);
+        assert_eq!(controller.drone.mode, Mode::Manual);
+        let panic = Command::ModeChange { mode: Mode::Panic };
+        assert_eq!(answers(&receive(&mut controller, &serialize_message_seq(panic, 1))), [Command::Ack { seq: 1 }]);
+        // with the motors off panic mode ends in safe mode right away
+        receive(&mut controller, &[]);
+        assert_eq!(controller.drone.mode, Mode::Safe);
+
+        // the Ack of the first mode change got lost and it is sent again
+        assert_eq!(answers(&receive(&mut controller, &serialize_message_seq(manual.clone(), 0))), [Command::Ack { seq: 0 }]);
+        assert_eq!(controller.drone.mode, Mode::Safe);
+
+        // one that never arrived before is overtaken by a newer command and refused
+        let param = Command::ParamSet { id: 0, value: I22F10::from_num(3) };
+        assert_eq!(answers(&receive(&mut controller, &serialize_message_seq(param, 3))), [Command::Ack { seq: 3 }]);
+        assert_eq!(answers(&receive(&mut controller, &serialize_message_seq(manual.clone(), 2))), [Command::Nack { seq: 2 }]);
+        assert_eq!(controller.drone.mode, Mode::Safe);
+
+        // a runner that connects again starts over
+        receive(&mut controller, &serialize_message(Command::Hello { revision: share_lib::PROTOCOL_REVISION }));
+        assert_eq!(answers(&receive(&mut controller, &serialize_message_seq(manual, 0))), [Command::Ack { seq: 0 }]);
+        assert_eq!(controller.drone.mode, Mode::Manual);
+    }
+
+    // manual mode with the throttle up (lift is negative), the runner keeps the link alive every tick until the drone
+    // is past the first 200 ticks in which it does not panic
+    fn flying() -> Controller<MockHal> {
+        let mut controller = Controller::new(MockHal::new());
+        receive(&mut controller, &serialize_message_seq(Command::ModeChange { mode: Mode::Manual }, 0));
+        receive(&mut controller, &serialize_message(Command::ThrottleSet { num: -400 }));
+        while controller.drone.tick <= 200 {
+            receive(&mut controller, &serialize_message(Command::KeepAlive));
+        }
+        controller
+    }
+
+    #[test]
+    fn telemetry_every_period() {
+        let mut controller = Controller::new(MockHal::new());
+        let period = controller.drone.telemetry_period as u32;
+        let mut ticks = Vec::new();
+        for _ in 0..3 * period {
+            for sent in receive(&mut controller, &[]) {
+                if let Command::Telemetry { data } = sent {
+                    ticks.push(data.tick);
+                }
+            }
+        }
+        assert_eq!(ticks, [0, period, 2 * period]);
+    }
+
+    #[test]
+    fn manual_throttle_drives_the_motors() {
+        let mut controller = flying();
+        assert_eq!(controller.drone.mode, Mode::Manual);
+        let motors = controller.drone.hal.motors;
+        assert!(motors[0] > 0);
+        assert!(motors.iter().all(|&motor| motor == motors[0]));
+
+        receive(&mut controller, &serialize_message_seq(Command::ModeChange { mode: Mode::Safe }, 1));
+        assert_eq!(controller.drone.mode, Mode::Safe);
+        assert_eq!(controller.drone.hal.motors, [0; 4]);
+    }
+
+    #[test]
+    fn lost_link_starts_the_descent() {
+        let mut controller = flying();
+        let motors = controller.drone.hal.motors[0];
+        // 20 battery checks, one every 5 ticks, without a byte from the runner
+        for _ in 0..100 {
+            receive(&mut controller, &[]);
+        }
+        assert_eq!(controller.drone.mode, Mode::Panic);
+        receive(&mut controller, &[]);
+        assert!(controller.drone.hal.motors[0] > 0 && controller.drone.hal.motors[0] < motors);
+        // the runner is back, but the drone only lands
+        receive(&mut controller, &serialize_message_seq(Command::ModeChange { mode: Mode::Manual }, 1));
+        assert_eq!(controller.drone.mode, Mode::Panic);
+        while controller.drone.mode == Mode::Panic {
+            receive(&mut controller, &serialize_message(Command::KeepAlive));
+        }
+        assert_eq!(controller.drone.mode, Mode::Safe);
+        assert_eq!(controller.drone.hal.motors, [0; 4]);
+    }
+
+    #[test]
+    fn low_battery_starts_the_descent() {
+        let mut controller = flying();
+        controller.drone.hal.battery = 1000;
+        for _ in 0..5 {
+            receive(&mut controller, &serialize_message(Command::KeepAlive));
+        }
+        assert_eq!(controller.drone.mode, Mode::Panic);
+    }
+}
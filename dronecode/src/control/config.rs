@@ -0,0 +1,172 @@
+use fixed::types::I22F10;
+use share_lib::crc16;
+use crate::control::drone::Drone;
+use crate::control::fsm::basic_modes::Descent;
+use crate::control::fsm::height::Height;
+use crate::control::pid::{PID, RATE_LIMIT, YAW_LIMIT};
+use crate::control::mixer::Mixer;
+use crate::control::yaw_pitch_roll::YawPitchRoll;
+use crate::control::params::PARAM_COUNT;
+use crate::hal::{DroneHal, FlashError};
+
+//the parameters and the calibration are kept in the last 4KB of the flash, the datalog stops below it.
+//flash can only be erased as a whole chip and a write can only clear bits, so every save goes to the
+//next empty slot and the newest slot is the one that counts
+pub const CONFIG_ADDRESS: u32 = 0x01F000;
+const CONFIG_SLOT_LEN: usize = 512;
+const CONFIG_SLOTS: u32 = 8;
+
+//first byte of a slot: the layout version of a block, never written, or a reset back to the defaults.
+//bump the version whenever the layout changes, appended parameters do not change it
+const CONFIG_VERSION: u8 = 2;
+const EMPTY: u8 = 0xFF;
+const CLEARED: u8 = 0x00;
+
+//version, number of parameters, calibration values, parameter values, CRC of everything before it
+const HEADER_LEN: usize = 2;
+const CALIBRATION_LEN: usize = 11;
+const MAX_PARAMS: usize = (CONFIG_SLOT_LEN - HEADER_LEN - 2) / 4 - CALIBRATION_LEN;
+//a parameter that does not fit would silently not be saved
+const _: () = assert!(PARAM_COUNT <= MAX_PARAMS);
+
+fn slot_address(slot: u32) -> u32 {
+    CONFIG_ADDRESS + slot * CONFIG_SLOT_LEN as u32
+}
+
+fn block_len(params: usize) -> usize {
+    HEADER_LEN + 4 * (CALIBRATION_LEN + params)
+}
+
+//the slot the next block goes to, None when the region is full
+fn free_slot(hal: &mut impl DroneHal) -> Option<u32> {
+    (0..CONFIG_SLOTS).find(|&slot| {
+        let mut first = [CLEARED];
+        hal.flash_read(slot_address(slot), &mut first).is_ok() && first[0] == EMPTY
+    })
+}
+
+//reads the newest block that passes its check into block, false when nothing was saved or the
+//config was reset. The caller owns the buffer, a returned block would be copied on the small stack
+fn latest_block(hal: &mut impl DroneHal, block: &mut [u8; CONFIG_SLOT_LEN]) -> bool {
+    let used = free_slot(hal).unwrap_or(CONFIG_SLOTS);
+    for slot in (0..used).rev() {
+        if hal.flash_read(slot_address(slot), block).is_err() {
+            return false;
+        }
+        let len = block_len(block[1] as usize);
+        match block[0] {
+            CLEARED => return false,
+            CONFIG_VERSION if block[1] as usize <= MAX_PARAMS && crc16(&block[..len]) == block[len..len + 2] => {
+                return true;
+            }
+            // a write cut short by a reset, or a layout this firmware does not know, try the one before
+            _ => {}
+        }
+    }
+    false
+}
+
+//chip erase for the datalog, the config block is written back afterwards
+pub fn erase_keeping_config(hal: &mut impl DroneHal) -> Result<(), FlashError> {
+    let mut block = [EMPTY; CONFIG_SLOT_LEN];
+    let saved = latest_block(hal, &mut block);
+    hal.flash_erase()?;
+    if saved {
+        hal.flash_write(slot_address(0), &block)
+    } else {
+        Ok(())
+    }
+}
+
+impl<H: DroneHal> Drone<H> {
+    //store the parameters and the calibration, returns false when the flash fails.
+    //Not inlined, the block would otherwise take up stack under every other command
+    #[inline(never)]
+    pub fn save_config(&mut self) -> bool {
+        let mut block = [EMPTY; CONFIG_SLOT_LEN];
+        self.config_block(&mut block);
+        self.write_config(&block)
+    }
+
+    //go back to the saved parameters and calibration, returns false when there are none
+    pub fn load_config(&mut self) -> bool {
+        let mut block = [EMPTY; CONFIG_SLOT_LEN];
+        let saved = latest_block(&mut self.hal, &mut block);
+        if saved {
+            self.apply_config_block(&block);
+        }
+        saved
+    }
+
+    //go back to the defaults, also after the next boot. Not inlined like save_config
+    #[inline(never)]
+    pub fn reset_config(&mut self) -> bool {
+        self.yaw_pid = PID::new(YAW_LIMIT);
+        self.pitch_pid = PID::new(RATE_LIMIT);
+        self.roll_pid = PID::new(RATE_LIMIT);
+        self.pitch_rate_pid = PID::rate_loop();
+        self.roll_rate_pid = PID::rate_loop();
+        self.calibration_ypr = YawPitchRoll::new();
+        self.calibration_ypr_raw = YawPitchRoll::new();
+        self.raw_data.reset();
+        self.height = Height::new();
+        self.mixer = Mixer::new();
+        self.descent = Descent::new();
+        self.write_config(&[CLEARED])
+    }
+
+    fn write_config(&mut self, bytes: &[u8]) -> bool {
+        let slot = match free_slot(&mut self.hal) {
+            Some(slot) => slot,
+            // only a chip erase makes room again, and that takes the datalog along
+            None => {
+                if self.hal.flash_erase().is_err() {
+                    return false;
+                }
+                self.current_point = 0;
+                0
+            }
+        };
+        self.hal.flash_write(slot_address(slot), bytes).is_ok()
+    }
+
+    fn calibration(&self) -> [I22F10; CALIBRATION_LEN] {
+        let YawPitchRoll { yaw, pitch, roll } = self.calibration_ypr;
+        let raw = self.calibration_ypr_raw;
+        let bias = self.raw_data.rate_bias;
+        [yaw, pitch, roll, raw.yaw, raw.pitch, raw.roll, bias.yaw, bias.pitch, bias.roll,
+            self.raw_data.gravity, self.height.calibration_p]
+    }
+
+    fn config_block(&self, block: &mut [u8; CONFIG_SLOT_LEN]) {
+        let params = Self::PARAMS.len();
+        let values = self.calibration().into_iter()
+            .chain(Self::PARAMS.iter().take(params).map(|param| (param.get)(self)));
+        block[0] = CONFIG_VERSION;
+        block[1] = params as u8;
+        for (bytes, value) in block[HEADER_LEN..].chunks_exact_mut(4).zip(values) {
+            bytes.copy_from_slice(&value.to_bits().to_le_bytes());
+        }
+        let len = block_len(params);
+        let crc = crc16(&block[..len]);
+        block[len..len + 2].copy_from_slice(&crc);
+    }
+
+    //a block saved before parameters were appended leaves the new ones at their current value
+    fn apply_config_block(&mut self, block: &[u8; CONFIG_SLOT_LEN]) {
+        let value = |index: usize| {
+            let start = HEADER_LEN + 4 * index;
+            I22F10::from_bits(i32::from_le_bytes([block[start], block[start + 1], block[start + 2], block[start + 3]]))
+        };
+        let [yaw, pitch, roll, raw_yaw, raw_pitch, raw_roll, bias_yaw, bias_pitch, bias_roll, gravity, pressure]: [I22F10; CALIBRATION_LEN] =
+            core::array::from_fn(value);
+        self.calibration_ypr = YawPitchRoll { yaw, pitch, roll };
+        self.calibration_ypr_raw = YawPitchRoll { yaw: raw_yaw, pitch: raw_pitch, roll: raw_roll };
+        self.raw_data.rate_bias = YawPitchRoll { yaw: bias_yaw, pitch: bias_pitch, roll: bias_roll };
+        self.raw_data.gravity = gravity;
+        self.height.calibration_p = pressure;
+        for (id, param) in Self::PARAMS.iter().enumerate().take(block[1] as usize) {
+            (param.set)(self, value(CALIBRATION_LEN + id).clamp(param.min, param.max));
+        }
+    }
+}
@@ -1,29 +1,73 @@
-use tudelft_quadrupel::flash::{flash_chip_erase, flash_read_bytes, flash_write_bytes};
-use share_lib::{Command, Message};
+use share_lib::{Command, Message, LOG_FEC_RECORD_LEN, LOG_RECORD_LEN};
+use crate::control::config::{erase_keeping_config, CONFIG_ADDRESS};
 use crate::control::drone::Drone;
+use crate::hal::DroneHal;
 
-//data log write
-pub fn datalog(cmd:Command, pc_counter: u32) ->u32{
-    let mut mes = Message::new(cmd);
+//the log takes the flash up to the config region
+pub const LOG_END: u32 = CONFIG_ADDRESS;
+
+//data log write, an LDPC encoded record can have bit errors in flash corrected but takes twice the room.
+//Not inlined, the encoding buffers stay out of the stack frame of the control loop
+#[inline(never)]
+pub fn datalog(hal: &mut impl DroneHal, cmd:Command, pc_counter: u32, fec: bool) ->u32{
+    let mut mes = if fec { Message::with_fec(cmd) } else { Message::new(cmd) };
     let serialized = mes.build_message_log();
-    let (point,new_pont) = full_check(pc_counter,64);
-    flash_write_bytes(point, &serialized).expect("log fail");
+    let (point,new_pont) = full_check(hal, pc_counter,serialized.len() as u32);
+    hal.flash_write(point, &serialized).expect("log fail");
     new_pont
 }
 
-//erase the flash
-pub fn full_check(pc_counter: u32, len: u32) -> (u32,u32){
+//erase the flash, the config block survives it
+pub fn full_check(hal: &mut impl DroneHal, pc_counter: u32, len: u32) -> (u32,u32){
     let mut result = pc_counter;
-    if (pc_counter + len) > 0x01FFFE{
+    if (pc_counter + len) > LOG_END{
         result = 0x000000;
-        flash_chip_erase().expect("erase fail");
+        erase_keeping_config(hal).expect("erase fail");
     }
     (result, result+len)
 }
-impl Drone {
-    pub fn pick_up_message(&mut self) -> Option<Command>{
-        let mut buf= [0;64];
-        flash_read_bytes(self.pc_counter, &mut buf).expect("read flash fail");
-        Message::get_message_log(&buf)
+impl<H: DroneHal> Drone<H> {
+    //the record at pc_counter and the room it takes. log_fec can be switched while logging, so a record
+    //is read as a plain one first and as an LDPC encoded one when that fails
+    pub fn pick_up_message(&mut self) -> (Option<Command>, u32){
+        let mut buf= [0;LOG_FEC_RECORD_LEN];
+        let len = LOG_FEC_RECORD_LEN.min((LOG_END - self.pc_counter) as usize);
+        self.hal.flash_read(self.pc_counter, &mut buf[..len]).expect("read flash fail");
+        if let Ok(cmd) = Message::get_message_log(&buf[..LOG_RECORD_LEN]) {
+            return (Some(cmd), LOG_RECORD_LEN as u32);
+        }
+        match Message::get_message_log(&buf[..len]) {
+            Ok(cmd) => (Some(cmd), LOG_FEC_RECORD_LEN as u32),
+            Err(_) => (None, LOG_RECORD_LEN as u32),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use crate::hal::MockHal;
+    use super::*;
+
+    #[test]
+    fn log_fec_can_be_switched_while_logging() {
+        let mut drone = Drone::with_hal(MockHal::new());
+        let mut point = 0;
+        for (tick, fec) in [(1, false), (2, true), (3, true), (4, false)] {
+            point = datalog(&mut drone.hal, Command::Datalog { mode: share_lib::Mode::Safe, ypr: [0.0; 3], raw_ypr: [0.0; 3],
+                motor: [0; 4], time: 0, raw_speed: 0.0, tick }, point, fec);
+        }
+        assert_eq!(point as usize, 2 * LOG_RECORD_LEN + 2 * LOG_FEC_RECORD_LEN);
+        // a bit error in an encoded record is corrected on the way out
+        drone.hal.flash[LOG_RECORD_LEN + 3] ^= 0x10;
+        let mut ticks = Vec::new();
+        while (drone.pc_counter as usize) < point as usize {
+            let (record, len) = drone.pick_up_message();
+            if let Some(Command::Datalog { tick, .. }) = record {
+                ticks.push(tick);
+            }
+            drone.pc_counter += len;
+        }
+        assert_eq!(ticks, [1, 2, 3, 4]);
     }
 }
@@ -1,17 +1,18 @@
-use share_lib::{Command, serialize_message, Mode};
-use tudelft_quadrupel::led::Led::{self, Yellow};
-use tudelft_quadrupel::uart::send_bytes;
-use tudelft_quadrupel::block;
-use tudelft_quadrupel::mpu::{read_dmp_bytes};
+use core::mem::MaybeUninit;
+use share_lib::{Command, serialize_message, serialize_message_fec, Mode, DEFAULT_TELEMETRY_PERIOD, LOG_RECORD_LEN};
 use fixed::types::I22F10;
-use tudelft_quadrupel::motor::set_motor_max;
-use crate::control::pid::PID;
+use crate::control::pid::{PID, RATE_LIMIT, YAW_LIMIT};
 use crate::control::yaw_pitch_roll::YawPitchRoll;
 use crate::control::fsm::raw::RawData;
 use crate::control::fsm::height::Height;
+use crate::control::fsm::basic_modes::Descent;
+use crate::control::info::send_hello_ack;
+use crate::control::mixer::{Mixer, Saturation, MOTOR_MAX};
+use crate::control::datalog::LOG_END;
+use crate::hal::{DefaultHal, DroneHal, Led};
 
-
-pub struct Drone {
+pub struct Drone<H: DroneHal = DefaultHal> {
+    pub hal: H, // the board the drone runs on
     pub mode: Mode,
     pub js_ypr: YawPitchRoll, // YPR reference from joystik
     pub js_t: i16, // Throttle reference from joystick
@@ -19,7 +20,6 @@ pub struct Drone {
     pub prev_sensor_ypr: YawPitchRoll, // Previous measured YPR on drone
     pub calibration_ypr: YawPitchRoll, // Calibration YPR
     pub calibration_ypr_raw: YawPitchRoll, // RAW Calibration YPR
-    pub prev_error_ypr: YawPitchRoll, // Previous error for D-control
     pub prev_sensor_ypr_control: YawPitchRoll, // Previous measured YPR on drone
     pub sensor_ypr: YawPitchRoll, // Current YPR on drone
     pub motor_ypr: YawPitchRoll, // YPRT that is send to motor control
@@ -27,6 +27,8 @@ pub struct Drone {
     pub yaw_pid: PID, // PID values for the yaw control
     pub pitch_pid: PID, // PID values for the pitch control
     pub roll_pid: PID, // PID values for the roll control
+    pub pitch_rate_pid: PID, // inner loop of the pitch control
+    pub roll_rate_pid: PID, // inner loop of the roll control
 
     pub yaw_offset: I22F10, // Offset for the yaw control
     pub roll_offset: I22F10, // Offset for the roll control
@@ -35,46 +37,124 @@ pub struct Drone {
     pub current_point:u32,
     pub raw_data: RawData,
     pub height:Height,
+    pub battery: u16, // last battery reading
+    pub telemetry_period: u16, // ticks between telemetry frames, 0 is off
+    pub mixer: Mixer,
+    pub descent: Descent, // the way down in panic mode
+    pub param_cursor: Option<u8>, // next parameter to describe while answering ParamList
+    pub link_fec: bool, // send telemetry and log dumps LDPC encoded
+    pub log_fec: bool, // write the datalog LDPC encoded
+    pub boot: u64, // time base of Datalog.time and the clock synchronisation, in us
+    pub tick: u32, // current iteration of the control loop
 }
 
+#[cfg(feature = "quadrupel")]
 impl Drone {
+    //a drone on the quadrupel board, the drivers must already be initialized
     pub fn new() -> Self {
-        set_motor_max(1000);
-        Drone {
-            mode: Mode::Safe,
-            js_ypr: YawPitchRoll::new(),
-            js_t: 0,
-            prev_sensor_ypr: YawPitchRoll::new(),
-            prev_sensor_ypr_control: YawPitchRoll::new(),
-            calibration_ypr: YawPitchRoll::new(),
-            calibration_ypr_raw:YawPitchRoll::new(),
-            prev_error_ypr: YawPitchRoll::new(),
-            sensor_ypr: YawPitchRoll::new(),
-            motor_ypr: YawPitchRoll::new(),
-            yaw_pid: PID::new(),
-            pitch_pid: PID::new(),
-            roll_pid: PID::new(),
-            yaw_offset: I22F10::from_num(0),
-            roll_offset: I22F10::from_num(0),
-            prev_time: I22F10::from_num(0),
-            pc_counter:0,
-            current_point:0,
-            raw_data:RawData::new(),
-            height:Height::new(),
-        }
+        Self::with_hal(DefaultHal::new())
+    }
+}
+
+impl<H: DroneHal> Drone<H> {
+    pub fn with_hal(hal: H) -> Self {
+        let mut drone = MaybeUninit::uninit();
+        Self::init(&mut drone, hal);
+        // SAFETY: init writes every field
+        unsafe { drone.assume_init() }
+    }
+
+    //builds the drone in place, on the board it lives in a static
+    pub fn init(slot: &mut MaybeUninit<Self>, mut hal: H) -> &mut Self {
+        hal.set_motor_max(MOTOR_MAX);
+        let boot = hal.now_us();
+        // SAFETY: the pointer comes from the slot, and every field is written
+        let drone = unsafe {
+            write_fields!(slot.as_mut_ptr(), Self {
+                hal,
+                mode: Mode::Safe,
+                js_ypr: YawPitchRoll::new(),
+                js_t: 0,
+                prev_sensor_ypr: YawPitchRoll::new(),
+                prev_sensor_ypr_control: YawPitchRoll::new(),
+                calibration_ypr: YawPitchRoll::new(),
+                calibration_ypr_raw:YawPitchRoll::new(),
+                sensor_ypr: YawPitchRoll::new(),
+                motor_ypr: YawPitchRoll::new(),
+                yaw_pid: PID::new(YAW_LIMIT),
+                pitch_pid: PID::new(RATE_LIMIT),
+                roll_pid: PID::new(RATE_LIMIT),
+                pitch_rate_pid: PID::rate_loop(),
+                roll_rate_pid: PID::rate_loop(),
+                yaw_offset: I22F10::from_num(0),
+                roll_offset: I22F10::from_num(0),
+                prev_time: I22F10::from_num(0),
+                pc_counter:0,
+                current_point:0,
+                raw_data:RawData::new(),
+                height:Height::new(),
+                battery: 0,
+                telemetry_period: DEFAULT_TELEMETRY_PERIOD,
+                mixer: Mixer::new(),
+                descent: Descent::new(),
+                param_cursor: None,
+                link_fec: false,
+                log_fec: false,
+                boot,
+                tick: 0,
+            });
+            slot.assume_init_mut()
+        };
+        //gains and calibration of the last session
+        drone.load_config();
+        drone
     }
 
-    pub fn process_command(&mut self, cmd:Command){
+    //returns whether the command was carried out, which is reported back for acknowledged commands
+    pub fn process_command(&mut self, cmd:Command) -> bool{
+        //the handshake, telemetry and parameters are handled in every mode
+        match cmd {
+            Command::Hello { .. } => {
+                send_hello_ack(&mut self.hal);
+                return true;
+            }
+            Command::TelemetryPeriod { ticks } => {
+                self.telemetry_period = ticks;
+                return true;
+            }
+            Command::ParamSet { id, value } => return self.set_param(id, value),
+            Command::ParamGet { id } => return self.send_param(id),
+            Command::ParamList => {
+                self.param_cursor = Some(0);
+                return true;
+            }
+            Command::LinkFec { enabled } => {
+                self.link_fec = enabled;
+                return true;
+            }
+            Command::TimeSyncRequest { pc_time_us } => {
+                let drone_time_us = self.hal.now_us() - self.boot;
+                self.hal.send_bytes(&serialize_message(Command::TimeSyncReply { pc_time_us, drone_time_us, tick: self.tick }));
+                return true;
+            }
+            _ => {}
+        }
         if (self.mode == Mode::Manual)||(self.mode == Mode::YawControlled)||
             (self.mode == Mode::FullControl)||(self.mode == Mode::Raw)||(self.mode == Mode::Height){
             // self.commandmatch(cmd);
             match cmd {
                 Command::ModeChange { mode:m}=> {
                     self.mode_match(m);
+                    self.mode == m
+                }
+                //the pc is shutting down, bring the drone down
+                Command::EXIT => {
+                    self.mode_match(Mode::Panic);
+                    true
                 }
                 //message timeout test add, new situation!
                 _ => {
-                    self.commandmatch(cmd);
+                    self.commandmatch(cmd)
                 },
             }
 
@@ -84,22 +164,70 @@ impl Drone {
                     if self.mode != mode {
                         self.mode_match(mode);
                     }
+                    self.mode == mode
+                }
+                Command::EXIT => true,
+                //the loaded values are sent back as if the parameters were listed
+                Command::ConfigSave if self.mode == Mode::Safe => self.save_config(),
+                Command::ConfigLoad if self.mode == Mode::Safe => {
+                    self.param_cursor = Some(0);
+                    self.load_config()
                 }
-                _ => {},
+                Command::ConfigReset if self.mode == Mode::Safe => {
+                    self.param_cursor = Some(0);
+                    self.reset_config()
+                }
+                _ => false,
             }
         }
     }
 
     pub fn mode_match(&mut self, mode:Mode){
-        Led::Red.off();
-        Led::Green.off();
-        Yellow.off();
+        self.hal.led_off(Led::Red);
+        self.hal.led_off(Led::Green);
+        self.hal.led_off(Led::Yellow);
+        let previous = self.mode;
         self.mode_limit_check(mode);
-        send_bytes(&serialize_message(Command::ModeChange { mode: self.mode }));
+        //a mode starts without the integral and derivative history of the previous one
+        if self.mode != previous {
+            if previous == Mode::Height {
+                self.height.leave();
+            }
+            self.yaw_pid.reset();
+            self.pitch_pid.reset();
+            self.roll_pid.reset();
+            self.pitch_rate_pid.reset();
+            self.roll_rate_pid.reset();
+            self.height.pid.reset();
+            self.mixer.saturation = Saturation::default();
+            if self.mode == Mode::Height {
+                self.height.enter(self.js_t, self.mixer.min_throttle);
+            }
+            //the descent starts from the throttle that was flown, there is none when the motors were off
+            if self.mode == Mode::Panic {
+                let throttle = if previous == Mode::Height { self.height.current_throttle } else { I22F10::from_num(self.js_t) };
+                let flying = self.hal.get_motors() != [0, 0, 0, 0];
+                self.descent.start(if flying { throttle } else { I22F10::from_num(0) });
+            }
+        }
+        self.hal.send_bytes(&serialize_message(Command::ModeChange { mode: self.mode }));
+    }
+
+    //attitude estimate the current mode flies on
+    pub fn attitude(&self) -> YawPitchRoll {
+        if self.mode == Mode::Raw {
+            self.raw_data.current_ypr
+        } else {
+            self.sensor_ypr
+        }
     }
 
     pub fn read_sensor_ypr(&mut self){
-        let quaternion = block!(read_dmp_bytes()).unwrap();
+        let quaternion = if self.raw_data.replace_dmp {
+            self.raw_data.mahony.quaternion()
+        } else {
+            self.hal.read_dmp()
+        };
         let sensor_ypr = YawPitchRoll::from(quaternion);
         self.prev_sensor_ypr = self.sensor_ypr;
 
@@ -128,6 +256,8 @@ impl Drone {
         }
     }
 
+    //not inlined, the control loop calls process_command next to it and their frames would add up
+    #[inline(never)]
     pub fn operate(&mut self, _dt: u128){
         match self.mode {
             Mode::Safe => {
@@ -155,15 +285,20 @@ impl Drone {
                 self.height_operate();
             },
             Mode::LogOut=>{
-                if self.pc_counter < 0x01FFFE {
-                    match self.pick_up_message() {
+                if self.pc_counter + LOG_RECORD_LEN as u32 <= LOG_END {
+                    let (record, len) = self.pick_up_message();
+                    match record {
                         None => {}
                         Some(cmd) => {
-                            Yellow.toggle();
-                            send_bytes(&serialize_message(cmd));
+                            self.hal.led_toggle(Led::Yellow);
+                            if self.link_fec {
+                                self.hal.send_bytes(&serialize_message_fec(cmd));
+                            } else {
+                                self.hal.send_bytes(&serialize_message(cmd));
+                            }
                         }
                     }
-                    self.pc_counter += 64;
+                    self.pc_counter += len;
                 }else {
                     self.pc_counter = 0x000000
                 }
@@ -173,17 +308,17 @@ impl Drone {
     }
 
 
-    fn commandmatch(&mut self, cmd: Command){
+    fn commandmatch(&mut self, cmd: Command) -> bool{
         match cmd {
             Command::ThrottleSet{num}=>{
                 self.js_t = num;
-                send_bytes(&serialize_message(Command::ThrottleSet {num}));
+                self.hal.send_bytes(&serialize_message(Command::ThrottleSet {num}));
             }
             Command::YawSet{num}=>{
                 self.js_ypr.yaw = I22F10::from_bits(num);
                 let mut mes = share_lib::Message::new(Command::PitchSet {num});
                 let serialized = mes.build_message();
-                send_bytes(&serialized);
+                self.hal.send_bytes(&serialized);
             }
             Command::PitchSet{num}=>{
                 self.js_ypr.pitch = I22F10::from_bits(num);
@@ -193,39 +328,46 @@ impl Drone {
             },
             Command::YawPSet{num}=>{
                 self.yaw_pid.p = I22F10::from_num(num);
-                send_bytes(&serialize_message(Command::YawPSet {num:self.yaw_pid.p.to_num()}));
+                self.hal.send_bytes(&serialize_message(Command::YawPSet {num:self.yaw_pid.p.to_num()}));
             }
             Command::YawDSet{num}=>{
                 self.yaw_pid.d = I22F10::from_num(num);
-                send_bytes(&serialize_message(Command::YawDSet {num:self.yaw_pid.d.to_num()}));
+                self.hal.send_bytes(&serialize_message(Command::YawDSet {num:self.yaw_pid.d.to_num()}));
             }
             Command::PitchPSet{num}=>{
                 self.pitch_pid.p = I22F10::from_num(num);
-                send_bytes(&serialize_message(Command::PitchPSet {num:self.pitch_pid.p.to_num()}));
+                self.hal.send_bytes(&serialize_message(Command::PitchPSet {num:self.pitch_pid.p.to_num()}));
             }
             Command::PitchDSet{num}=>{
                 self.pitch_pid.d = I22F10::from_num(num);
-                send_bytes(&serialize_message(Command::PitchDSet {num:self.pitch_pid.d.to_num()}));
+                self.hal.send_bytes(&serialize_message(Command::PitchDSet {num:self.pitch_pid.d.to_num()}));
             }
             Command::RollPSet{num}=>{
                 self.roll_pid.p = I22F10::from_num(num);
-                send_bytes(&serialize_message(Command::RollPSet {num:self.roll_pid.p.to_num()}));
+                self.hal.send_bytes(&serialize_message(Command::RollPSet {num:self.roll_pid.p.to_num()}));
             }
             Command::RollDSet{num}=>{
                 self.roll_pid.d = I22F10::from_num(num);
-                send_bytes(&serialize_message(Command::RollDSet {num:self.roll_pid.d.to_num()}));
+                self.hal.send_bytes(&serialize_message(Command::RollDSet {num:self.roll_pid.d.to_num()}));
             }
             _=> {
-
+                return false;
             },
         }
-
+        true
     }
     pub fn mode_limit_check(&mut self,mode:Mode) {
-        //if we are in operation modes, we can only enter the panic or safe mode
+        //if we are in operation modes, we can only enter the panic or safe mode,
+        //altitude hold can also be switched on and off in flight
         if self.mode == Mode::Calibration || self.mode == Mode::Manual || self.mode
-            == Mode::YawControlled || self.mode == Mode::Raw || self.mode == Mode::FullControl{
-            if mode == Mode::Safe || mode == Mode::Panic{
+            == Mode::YawControlled || self.mode == Mode::Raw || self.mode == Mode::FullControl || self.mode == Mode::Height{
+            let altitude_hold = matches!((self.mode, mode), (Mode::FullControl, Mode::Height) | (Mode::Height, Mode::FullControl));
+            if mode == Mode::Safe || mode == Mode::Panic || altitude_hold{
+                self.mode = mode;
+            }
+        }else if self.mode == Mode::Panic {
+            //panic mode is not cut short, it ends in safe mode once the descent is over
+            if mode == Mode::Safe && self.descent.finished() {
                 self.mode = mode;
             }
         }else {
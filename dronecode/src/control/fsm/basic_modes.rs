@@ -1,37 +1,90 @@
-use tudelft_quadrupel::led::{ Red, Yellow};
-use tudelft_quadrupel::motor::{get_motors, set_motors};
-use tudelft_quadrupel::time::assembly_delay;
+use fixed::types::I22F10;
 use share_lib::{Command, Mode};
 use crate::control::drone::Drone;
+use crate::control::TICK_FREQ;
 use crate::control::yaw_pitch_roll::YawPitchRoll;
+use crate::hal::{DroneHal, Led};
 
-impl Drone{
+//the way down in panic mode: the throttle the drone was flying with is held at a share of it for a while
+//and then ramped down until the motors are off. it takes one step per tick, so the link stays alive
+pub struct Descent {
+    pub throttle: I22F10, // share of the throttle at panic entry that is held, in percent
+    pub hold: I22F10, // time the share is held in s
+    pub ramp: I22F10, // time from the share down to the motors off in s
+    pub stay_level: bool, // keep the drone level with the attitude control on the way down
+    start: I22F10, // throttle at panic entry, 0 when the motors were off
+    ticks: u32, // ticks since panic entry
+}
+
+impl Descent {
+    pub fn new() -> Self {
+        Descent {
+            throttle: I22F10::from_num(70),
+            hold: I22F10::from_num(1),
+            ramp: I22F10::from_num(3),
+            stay_level: true,
+            start: I22F10::from_num(0),
+            ticks: 0,
+        }
+    }
+
+    pub fn start(&mut self, throttle: I22F10) {
+        self.start = throttle;
+        self.ticks = 0;
+    }
+
+    fn time(&self) -> I22F10 {
+        I22F10::from_num(self.ticks) / I22F10::from_num(TICK_FREQ)
+    }
+
+    pub fn finished(&self) -> bool {
+        self.start == 0 || self.time() >= self.hold + self.ramp
+    }
+
+    //the throttle of this tick, None once the ramp is over
+    pub fn next(&mut self) -> Option<I22F10> {
+        if self.finished() {
+            return None;
+        }
+        let time = self.time();
+        let mut share = self.throttle / 100;
+        if time > self.hold {
+            share -= share * (time - self.hold) / self.ramp;
+        }
+        self.ticks += 1;
+        Some(self.start * share)
+    }
+}
+
+impl<H: DroneHal> Drone<H> {
     pub fn panic_operate(&mut self){
         self.js_ypr = YawPitchRoll::new();
         self.js_t = 0;
-        Red.on();
-        let motors = get_motors();
-        let stage1 = (motors[0]+motors[1]+motors[2]+motors[3])/8;
-        set_motors([stage1,stage1,stage1,stage1]);//reduce motor speed
-        assembly_delay(200);//keep a little whil
-        let stage2 = stage1/2;
-        set_motors([stage2,stage2,stage2,stage2]);
-        assembly_delay(100);//keep a little whil
-        let stage3 = stage2/2;
-        set_motors([stage3,stage3,stage3,stage3]);
-        assembly_delay(100);//keep a little whil
-        let stage4 = stage3/2;
-        set_motors([stage4,stage4,stage4,stage4]);
-        assembly_delay(100);//keep a little whil
-        set_motors([0,0,0,0]);
-        Red.off();
-        self.process_command(Command::ModeChange { mode: Mode::Safe })
+        self.hal.led_on(Led::Red);
+        match self.descent.next() {
+            Some(throttle) => {
+                // with the joystick values cleared the attitude control holds the drone level
+                self.read_sensor_ypr();
+                if self.descent.stay_level {
+                    self.attitude_control(self.sensor_ypr);
+                } else {
+                    self.motor_ypr = YawPitchRoll::new();
+                }
+                self.hal.set_motors(self.mixer.motors(self.motor_ypr, throttle));
+                self.prev_sensor_ypr_control = self.sensor_ypr;
+            }
+            None => {
+                self.hal.set_motors([0,0,0,0]);
+                self.hal.led_off(Led::Red);
+                self.process_command(Command::ModeChange { mode: Mode::Safe });
+            }
+        }
     }
 
     pub fn safe_operate(&mut self){
-        Yellow.on();
-        set_motors([0,0,0,0]);
+        self.hal.led_on(Led::Yellow);
+        self.hal.set_motors([0,0,0,0]);
         self.js_ypr = YawPitchRoll::new();
         self.js_t = 0;
     }
-}
\ No newline at end of file
+}
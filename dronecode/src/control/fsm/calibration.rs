@@ -1,13 +1,12 @@
 use alloc::vec::Vec;
 use fixed::types::I22F10;
-use tudelft_quadrupel::barometer::read_pressure;
 use share_lib::Command;
 use crate::control::drone::Drone;
-use crate::control::info::send_calibration_vals;
 use crate::control::yaw_pitch_roll::YawPitchRoll;
+use crate::hal::DroneHal;
 
 
-impl Drone{
+impl<H: DroneHal> Drone<H> {
     pub fn calibration_operate(&mut self){
         self.roll_offset = I22F10::from_num(0);
         self.yaw_offset = I22F10::from_num(0);
@@ -15,13 +14,17 @@ impl Drone{
         let mut data_base_ypr = Vec::new();
         let mut data_base_raw = Vec::new();
         let mut data_base_height = Vec::new();
+        let mut data_base_rates = Vec::new();
+        let mut data_base_gravity = Vec::new();
         // store ypr and raw ypr
         for _i in 0..20 {
             self.read_sensor_ypr();
             self.read_raw_sensor();
-            data_base_height.push(I22F10::from_num(read_pressure()));
+            data_base_height.push(I22F10::from_num(self.hal.read_pressure()));
             data_base_ypr.push(self.sensor_ypr);
             data_base_raw.push(self.raw_data.current_ypr);
+            data_base_rates.push(self.raw_data.rates);
+            data_base_gravity.push(self.vertical_force());
         }
         // get the average value as the calibration value
         self.calibration_ypr_raw = YawPitchRoll{
@@ -32,10 +35,16 @@ impl Drone{
             yaw:data_base_ypr.iter().map(|item| item.yaw).sum::<I22F10>() / I22F10::from_num(data_base_ypr.len()),
             pitch:data_base_ypr.iter().map(|item| item.pitch).sum::<I22F10>() / I22F10::from_num(data_base_ypr.len()),
             roll: data_base_ypr.iter().map(|item| item.roll).sum::<I22F10>() / I22F10::from_num(data_base_ypr.len()) };
-        //send back the calibration value to pc
-        send_calibration_vals(self.sensor_ypr);
 
-        self.height.calibration_p = data_base_height.iter().map(|item| item).sum::<I22F10>() / I22F10::from_num(data_base_raw.len());
+        self.raw_data.rate_bias = YawPitchRoll{
+            yaw:data_base_rates.iter().map(|item| item.yaw).sum::<I22F10>() / I22F10::from_num(data_base_rates.len()),
+            pitch:data_base_rates.iter().map(|item| item.pitch).sum::<I22F10>() / I22F10::from_num(data_base_rates.len()),
+            roll: data_base_rates.iter().map(|item| item.roll).sum::<I22F10>() / I22F10::from_num(data_base_rates.len()) };
+
+        self.height.calibration_p = data_base_height.iter().sum::<I22F10>() / I22F10::from_num(data_base_raw.len());
+        self.raw_data.gravity = data_base_gravity.iter().sum::<I22F10>() / I22F10::from_num(data_base_gravity.len());
+        // the drone stands still on the calibration point
+        self.height.vertical.reset(I22F10::from_num(0));
 
         //go to the safe mode
         self.process_command(Command::ModeChange { mode: share_lib::Mode::Safe });
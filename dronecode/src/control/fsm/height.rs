@@ -1,137 +1,126 @@
-use alloc::vec::Vec;
 use fixed::types::I22F10;
-use tudelft_quadrupel::motor::set_motors;
-use tudelft_quadrupel::uart::send_bytes;
 use share_lib::{Command, serialize_message};
 use crate::control::drone::Drone;
-use crate::control::pid::PID;
-use crate::control::TICK_FREQ;
-use crate::control::utils::calc_motors;
-use crate::filters::butterworth::ButterWorth;
+use crate::control::pid::{PID, HEIGHT_LIMIT, HEIGHT_SCALE};
+use crate::control::{TICK_FREQ, TICK_US};
+use crate::filters::biquad::Iir;
+use crate::filters::vertical::VerticalEstimator;
+use crate::hal::DroneHal;
+
+//stick movement around the throttle at mode entry that does not move the setpoint
+const STICK_DEADBAND: i16 = 100;
+//climb rate per unit of stick movement beyond the deadband, in m/s
+const CLIMB_PER_STICK: f32 = 0.001;
+//highest lift the height controller asks for, the full throttle range of the joystick
+const MAX_LIFT: i32 = 2000;
+
+//height calculation: H = 44330 * [1 - (P/p0)^(1/5.255) ]
+//with x = 1 - P/p0 this is 44330/5.255 * x * (1 + (1 - 1/5.255)/2 * x + ...), the second order
+//term keeps the error below a centimetre over the first hundred metres
+pub fn pressure_to_altitude(pressure: I22F10, ground: I22F10) -> I22F10 {
+    if ground == 0 {
+        return I22F10::from_num(0);
+    }
+    let x = ((ground - pressure).to_num::<f32>()) / ground.to_num::<f32>();
+    I22F10::from_num(8435.8 * x * (1.0 + 0.40485 * x))
+}
 
 pub struct Height{
     pub pid:PID,
     pub current_throttle:I22F10,
-    pub prev_high:I22F10,
-    pub prev_error:I22F10,
-    pub current_high:I22F10,
-    pub calibration_p:I22F10,
-    pub butterworth:ButterWorth,
+    pub raw_altitude:I22F10, // altitude of the last barometer reading in m
+    pub altitude:I22F10, // altitude above the calibration point in m, from the barometer and accelerometer
+    pub velocity:I22F10, // vertical speed in m/s, up is positive
+    pub setpoint:I22F10, // altitude to hold in m
+    pub hover_lift:I22F10, // lift that keeps the drone in the air, learned by the integral
+    pub stick_center:i16, // throttle stick at mode entry
+    pub calibration_p:I22F10, // ground pressure in Pa
+    pub baro_filter:Iir,
+    pub vertical:VerticalEstimator,
 }
 
 impl Height{
     pub fn new()->Self{
        Height{
-           pid:PID::new(),
-           prev_high: I22F10::from_num(0),
+           pid:PID::with_gains(I22F10::from_num(10), I22F10::from_num(2), I22F10::from_num(20), HEIGHT_LIMIT),
            current_throttle: I22F10::from_num(0),
-           prev_error:I22F10::from_num(0),
-           current_high: I22F10::from_num(0),
+           raw_altitude: I22F10::from_num(0),
+           altitude: I22F10::from_num(0),
+           velocity: I22F10::from_num(0),
+           setpoint: I22F10::from_num(0),
+           hover_lift: I22F10::from_num(0),
+           stick_center: 0,
            calibration_p: I22F10::from_num(0),
-           butterworth: ButterWorth::new(),
+           baro_filter: Iir::low_pass(2, I22F10::from_num(10), I22F10::from_num(TICK_FREQ)),
+           vertical: VerticalEstimator::new(),
        }
     }
-    //height filter
-    pub fn height_update(&mut self){
-        self.current_high = self.butterworth.filter(self.current_high);
+
+    //new barometer reading in Pa
+    pub fn pressure_update(&mut self, pressure: I22F10){
+        self.raw_altitude = pressure_to_altitude(pressure, self.calibration_p);
+    }
+
+    //height estimate, runs every tick with the vertical acceleration in m/s^2
+    pub fn height_update(&mut self, accel: I22F10){
+        let baro = self.baro_filter.filter(self.raw_altitude);
+        self.vertical.update(accel, baro, TICK_US);
+        self.altitude = I22F10::from_num(self.vertical.altitude);
+        self.velocity = I22F10::from_num(self.vertical.velocity);
+    }
+
+    //hold the current altitude. In flight the throttle of the stick is what keeps the drone up,
+    //on the ground the hover lift learned before is used. min_throttle is where the mixer turns the motors on
+    pub fn enter(&mut self, js_t: i16, min_throttle: I22F10){
+        self.pid.reset();
+        self.setpoint = self.altitude;
+        self.stick_center = js_t;
+        if I22F10::from_num(js_t) <= min_throttle {
+            self.hover_lift = I22F10::from_num(-js_t);
+        }
+    }
+
+    //keep what the integral learned about the hover lift for the next time
+    pub fn leave(&mut self){
+        self.hover_lift += self.pid.integral();
+        self.pid.reset();
     }
 }
 
-impl Drone{
+impl<H: DroneHal> Drone<H> {
     pub fn calc_high_throttle(&mut self){
-        self.height.pid = PID{
-            p: I22F10::from_num(20),
-            i: I22F10::from_num(0),
-            d: I22F10::from_num(20),
+        // the stick away from where it was at mode entry moves the setpoint up or down
+        let stick = self.height.stick_center as i32 - self.js_t as i32;
+        let nudge = if stick > STICK_DEADBAND as i32 {
+            stick - STICK_DEADBAND as i32
+        } else if stick < -(STICK_DEADBAND as i32) {
+            stick + STICK_DEADBAND as i32
+        } else {
+            0
         };
-        let t_ref = I22F10::from_num(-self.js_t); // target height
-        let t_act = self.height.current_high*I22F10::from_num(50); // radians
-        // PD Controller for t_ref and t_act
-        let p_error = t_ref - t_act;
-        let d_error = (p_error - self.height.prev_error) *  I22F10::from_num(TICK_FREQ/100); // rad/micros Calculate derivative error
-
-        // Calculate the control signal              \/ CHANGE IF P NOT BIG / SMALL ENOUGH                    \/ CHANGE IF D NOT BIG / SMALL ENOUGH
-        self.height.current_throttle = -I22F10::from_num(0.05) * (self.height.pid.p)  * p_error;
-            // + I22F10::from_num(100) * self.height.pid.d * d_error; // Calculate control signal
-        send_bytes(&serialize_message(Command::Speed {num:self.height.current_throttle.to_bits()}));
-        self.height.prev_error = p_error; // Update last error for next iteration
-        self.height.prev_high = self.height.current_high;
-        // if self.height.pid.p < 1 && self.height.pid.d < 1{
-        if self.js_t > -185{
-            self.height.current_throttle = I22F10::from_num(self.js_t);
+        let climb = I22F10::from_num(nudge as f32 * CLIMB_PER_STICK * TICK_US as f32 / 1_000_000.0);
+        self.height.setpoint = (self.height.setpoint + climb).max(I22F10::from_num(0));
+
+        let correction = self.height.pid.update_with_rate(self.height.setpoint, self.height.altitude, self.height.velocity, &HEIGHT_SCALE, TICK_US);
+        let lift = (self.height.hover_lift + correction).clamp(I22F10::from_num(0), I22F10::from_num(MAX_LIFT));
+        // lift is a negative throttle
+        self.height.current_throttle = -lift;
+        self.hal.send_bytes(&serialize_message(Command::Speed {num:self.height.current_throttle.to_bits()}));
+        // the stick at the bottom still turns the motors off
+        if I22F10::from_num(self.js_t) > self.mixer.min_throttle {
+            self.height.current_throttle = I22F10::from_num(0);
         }
     }
 
     pub fn height_operate(&mut self){
         //get the value of ypr
         self.read_sensor_ypr();
-        //update the height value via filter
-        self.height.height_update();
-        //calculate the lift rate and then translate it to throttle value
+        //calculate the lift and then translate it to throttle value
         self.calc_high_throttle();
-        // + Yaw +
-
-        // Calculate the sensor and reference velocity
-        let sensor_velocity = I22F10::from_num(TICK_FREQ) * (self.sensor_ypr.yaw - self.prev_sensor_ypr_control.yaw);
-
-        //                                                                              \/ CHANGE IF JOYSTICK NOT / TOO SENSITIVE ENOUGH
-        let ref_velocity = I22F10::from_num(3) * self.js_ypr.yaw; // radians per seconds
-
-
-        // Calculate the PID value                \/ CHANGE IF P NOT BIG / SMALL ENOUGH
-        self.motor_ypr.yaw = I22F10::from_num(1) * self.yaw_pid.p * (ref_velocity - sensor_velocity);
-
-        if self.yaw_pid.p < 1 && self.yaw_pid.d < 1 {
-            self.motor_ypr.yaw = I22F10::from_num(100) * self.js_ypr.yaw;
-        }
-
-
-        // + Pitch +
-
-        let p_ref = self.js_ypr.pitch; // radians
-        let p_act = self.sensor_ypr.pitch; // radians
-
-        // PD Controller for p_ref and p_act
-        let p_error = p_ref - p_act;
-        let d_error = (p_error - self.prev_error_ypr.pitch) *  I22F10::from_num(TICK_FREQ/100); // rad/micros Calculate derivative error
-
-        // Calculate the control signal              \/ CHANGE IF P NOT BIG / SMALL ENOUGH                    \/ CHANGE IF D NOT BIG / SMALL ENOUGH
-        self.motor_ypr.pitch = I22F10::from_num(10) * self.pitch_pid.p * p_error + I22F10::from_num(100) * self.pitch_pid.d * d_error; // Calculate control signal
-
-        self.prev_error_ypr.pitch = p_error; // Update last error for next iteration
-
-        if self.pitch_pid.p < 1 && self.pitch_pid.d < 1{
-            self.motor_ypr.pitch = I22F10::from_num(500) * self.js_ypr.pitch;
-        }
-
-
-        // + Roll +
-
-        let r_ref = self.js_ypr.roll; // radians
-        let r_act = self.sensor_ypr.roll; // radians
-
-        // PD Controller for p_ref and p_act
-        let p_error = r_ref - r_act;
-        let d_error = (p_error - self.prev_error_ypr.roll)*I22F10::from_num(TICK_FREQ/100); // rad/micros Calculate derivative error
-
-        // Calculate the PID value               \/ CHANGE IF P NOT BIG / SMALL ENOUGH                      \/ CHANGE IF D NOT BIG / SMALL ENOUGH
-        self.motor_ypr.roll = I22F10::from_num(10) * self.roll_pid.p * p_error + I22F10::from_num(100) * self.roll_pid.d * d_error; // Calculate control signal
-
-        self.prev_error_ypr.roll = p_error; // Update last error for next iteration
-
-        if self.roll_pid.p < 1 && self.roll_pid.d < 1{
-            self.motor_ypr.roll = I22F10::from_num(500) * self.js_ypr.roll;
-        }
-
-
-        let mut temp = Vec::new();
-        temp.extend(serialize_message(Command::Trueyaw{num: self.sensor_ypr.yaw.to_bits()}));
-        temp.extend(serialize_message(Command::Truepitch{num: self.sensor_ypr.pitch.to_bits()}));
-        temp.extend(serialize_message(Command::Trueroll{num: self.sensor_ypr.roll.to_bits()}));
-        send_bytes(&temp);
+        self.attitude_control(self.sensor_ypr);
 
         // Send the motor values
-        set_motors(calc_motors(self.motor_ypr, self.height.current_throttle));
+        self.hal.set_motors(self.mixer.motors(self.motor_ypr, self.height.current_throttle));
         self.prev_sensor_ypr_control = self.sensor_ypr;
     }
-}
\ No newline at end of file
+}
@@ -1,138 +1,172 @@
-use alloc::vec::Vec;
+use core::mem::MaybeUninit;
 use fixed::types::I22F10;
-use tudelft_quadrupel::barometer::read_pressure;
-use tudelft_quadrupel::motor::set_motors;
-use tudelft_quadrupel::mpu::read_raw;
-use tudelft_quadrupel::uart::send_bytes;
-use share_lib::{Command, serialize_message, Mode};
+use share_lib::Mode;
 use crate::control::drone::Drone;
-use crate::control::TICK_FREQ;
-use crate::control::utils::calc_motors;
+use crate::control::{TICK_FREQ, TICK_US};
 use crate::control::yaw_pitch_roll::YawPitchRoll;
-use crate::filters::butterworth::ButterWorth;
+use crate::filters::biquad::Iir;
 use crate::filters::kalman::Kalman;
+use crate::filters::mahony::Mahony;
+use crate::hal::{Accel, DroneHal};
+use cordic::{cos, sin};
 const DEG2RAD:f32 = 0.017; //ref value for degree to radian (pi/180)
+const GYRO_LSB_PER_RAD: i32 = 940; //raw gyro reading for 1 rad/s, the MPU reads 16.4 per deg/s
+const ACCEL_LSB_PER_G: i32 = 16384; //raw accelerometer reading for 1 g
+const GRAVITY: f32 = 9.81; //in m/s^2
 pub struct RawData{
     pub prev_ypr:YawPitchRoll,
     pub current_ypr:YawPitchRoll,
     pub calibration_ypr_raw:YawPitchRoll,
     pub kalman:Kalman,
-    pub butterworth:ButterWorth,
+    pub mahony:Mahony,
+    pub mahony_ypr:YawPitchRoll, // last attitude of the mahony filter, between -pi and pi
+    pub kalman_raw:bool, // raw mode flies on the kalman pitch and roll instead of the mahony attitude
+    pub replace_dmp:bool, // the other modes fly on the mahony attitude instead of the motion processor
+    pub yaw_filter:Iir, // low-pass of the yaw rate flown on with the kalman filter
+    pub gyro_filter:Iir<2, 3>, // low-pass of the yaw, pitch and roll rates for the rate loops, off by default
+    pub gyro_notch:Iir<1, 3>, // notch of the yaw, pitch and roll rates for the rate loops, off by default
     pub roll_offset:I22F10,
+    pub yaw_offset:I22F10,
+    pub rates:YawPitchRoll, // yaw, pitch and roll rate from the gyro in rad/s
+    pub rate_bias:YawPitchRoll, // gyro rates while standing still, measured by the calibration
+    pub accel:Accel, // last raw accelerometer reading
+    pub gravity:I22F10, // vertical accelerometer reading while standing still, measured by the calibration
 }
 
 impl RawData{
+    fn filter_rates(&mut self) {
+        let [yaw, pitch, roll] = [self.rates.yaw, self.rates.pitch, self.rates.roll];
+        let mut filter = |axis: usize, rate: I22F10| self.gyro_notch.filter_channel(axis, self.gyro_filter.filter_channel(axis, rate));
+        self.rates = YawPitchRoll {
+            yaw: filter(0, yaw),
+            pitch: filter(1, pitch),
+            roll: filter(2, roll),
+        };
+    }
+
     pub fn new()->Self{
-        RawData{
+        let mut raw = MaybeUninit::uninit();
+        // SAFETY: write_defaults writes every field
+        unsafe {
+            Self::write_defaults(raw.as_mut_ptr());
+            raw.assume_init()
+        }
+    }
+
+    //back to the defaults in place, a second RawData would take up a lot of stack
+    pub fn reset(&mut self) {
+        // SAFETY: none of the fields needs to be dropped before it is overwritten
+        unsafe { Self::write_defaults(self) }
+    }
+
+    unsafe fn write_defaults(raw: *mut Self) {
+        write_fields!(raw, Self {
             prev_ypr: YawPitchRoll::new(),
             current_ypr:YawPitchRoll::new(),
             calibration_ypr_raw: YawPitchRoll::new(),
             kalman: Kalman::new(),
-            butterworth: ButterWorth::new(),
+            mahony: Mahony::new(),
+            mahony_ypr: YawPitchRoll::new(),
+            kalman_raw: false,
+            replace_dmp: false,
+            yaw_filter: Iir::low_pass(2, I22F10::from_num(10), I22F10::from_num(TICK_FREQ)),
+            gyro_filter: Iir::low_pass(2, I22F10::from_num(0), I22F10::from_num(TICK_FREQ)),
+            gyro_notch: Iir::notch(I22F10::from_num(0), I22F10::from_num(2), I22F10::from_num(TICK_FREQ)),
             roll_offset:I22F10::from_num(0),
-        }
+            yaw_offset:I22F10::from_num(0),
+            rates: YawPitchRoll::new(),
+            rate_bias: YawPitchRoll::new(),
+            accel: Accel::default(),
+            gravity: I22F10::from_num(ACCEL_LSB_PER_G),
+        });
     }
 }
 
-impl Drone {
-    pub fn raw_operate(&mut self){
-        self.read_raw_sensor();
-        read_pressure();
-        // + Yaw +
-
-        // Calculate the sensor and reference velocity
-        let sensor_velocity = self.raw_data.current_ypr.yaw;
-        //                                                                              \/ CHANGE IF JOYSTICK NOT / TOO SENSITIVE 
-        let ref_velocity = I22F10::from_num(3) * self.js_ypr.yaw; // radians per seconds
-
-
-        // Calculate the PID value                \/ CHANGE IF P NOT BIG / SMALL ENOUGH
-        self.motor_ypr.yaw = I22F10::from_num(0.7) * self.yaw_pid.p * (ref_velocity - sensor_velocity);
-
-        if self.yaw_pid.p < 1 && self.yaw_pid.d < 1{
-            self.motor_ypr.yaw = I22F10::from_num(100) * self.js_ypr.yaw;
-        }
+//keep an angle continuous when it wraps around from pi to -pi, the offset counts the full turns
+fn unwrap(prev: I22F10, angle: I22F10, offset: &mut I22F10) -> I22F10 {
+    if prev > I22F10::from_num(2.5) && angle < I22F10::from_num(-2.5) {
+        *offset += I22F10::PI*2;
+    }
+    else if prev < I22F10::from_num(-2.5) && angle > I22F10::from_num(2.5) {
+        *offset -= I22F10::PI*2;
+    }
+    angle + *offset
+}
 
-        // Send the motor values
-        
+impl<H: DroneHal> Drone<H> {
+    pub fn raw_operate(&mut self){
+        //the control loop already ran read_raw_sensor this tick, a second run would integrate the gyro twice
+        self.hal.read_pressure();
+        self.attitude_control(self.raw_data.current_ypr);
         self.prev_sensor_ypr_control = self.sensor_ypr;
-        // + Pitch +
-
-        let p_ref = self.js_ypr.pitch; // radians
-        let p_act = self.raw_data.current_ypr.pitch; // radians
 
-        // PD Controller for p_ref and p_act
-        let p_error = p_ref - p_act;
-        let d_error = (p_error - self.prev_error_ypr.pitch) *  I22F10::from_num(TICK_FREQ/100); // rad/micros Calculate derivative error
-        
-        
-        // Calculate the control signal              \/ CHANGE IF P NOT BIG / SMALL ENOUGH                    \/ CHANGE IF D NOT BIG / SMALL ENOUGH
-        self.motor_ypr.pitch = I22F10::from_num(15) * self.pitch_pid.p * p_error + I22F10::from_num(150) * self.pitch_pid.d * d_error; // Calculate control signal
-
-        self.prev_error_ypr.pitch = p_error; // Update last error for next iteration
-
-        if self.pitch_pid.p < 1 && self.pitch_pid.d < 1 {
-            self.motor_ypr.pitch = I22F10::from_num(500) * self.js_ypr.pitch;
-        }
-
-        // + Roll +
-
-        let r_ref = self.js_ypr.roll; // radians
-        let r_act = self.raw_data.current_ypr.roll; // radians
-
-        // PD Controller for p_ref and p_act
-        let p_error = r_ref - r_act;
-        let d_error = (p_error - self.prev_error_ypr.roll) * I22F10::from_num(TICK_FREQ/100); // rad/micros Calculate derivative error
-        
-        
-        // Calculate the PID value               \/ CHANGE IF P NOT BIG / SMALL ENOUGH                      \/ CHANGE IF D NOT BIG / SMALL ENOUGH
-        self.motor_ypr.roll = I22F10::from_num(10) * self.roll_pid.p * p_error + I22F10::from_num(100) * self.roll_pid.d * d_error; // Calculate control signal
-
-        self.prev_error_ypr.roll = p_error; // Update last error for next iteration
-
-        if self.roll_pid.p < 1 && self.roll_pid.d < 1{
-            self.motor_ypr.roll = I22F10::from_num(500) * self.js_ypr.roll;
-        }
-
-        let mut temp = Vec::new();
-        temp.extend(serialize_message(Command::Trueyaw{num: self.raw_data.current_ypr.yaw.to_bits()}));
-        temp.extend(serialize_message(Command::Truepitch{num: self.raw_data.kalman.pitchphi.to_bits()}));
-        temp.extend(serialize_message(Command::Trueroll{num: self.raw_data.kalman.rollphi.to_bits()}));
-        send_bytes(&temp);
-
-        set_motors(calc_motors(self.motor_ypr, I22F10::from_num(self.js_t)));
+        self.hal.set_motors(self.mixer.motors(self.motor_ypr, I22F10::from_num(self.js_t)));
 
     }
 
     pub fn read_raw_sensor(&mut self){
         self.raw_data.prev_ypr = self.raw_data.current_ypr;
-        let (acc, speed) = read_raw().unwrap();//get the raw sensor data
+        let (acc, speed) = self.hal.read_raw();//get the raw sensor data
+        self.raw_data.accel = acc;
         //transfer the raw sensor data to ideal value for calculation
         let acc_x = I22F10::from_num(acc.x);
         let acc_y = I22F10::from_num(acc.y);
         let acc_z = I22F10::from_num(acc.z);
-        let speed_x = I22F10::from_num(speed.x);
-        let speed_y = I22F10::from_num(speed.y);
         let mut speed_z = I22F10::from_num(speed.z)*I22F10::from_num(DEG2RAD);
-        self.raw_data.kalman.filtering(acc_x,acc_y,acc_z,speed_x,speed_y);
-        speed_z = self.raw_data.butterworth.filter(speed_z);
-        
-        // Check if the sensor has made a full rotation and add or subtract 2*PI
-        if (self.raw_data.prev_ypr.roll - self.raw_data.roll_offset) > I22F10::from_num(2.5) && self.raw_data.kalman.rollphi < I22F10::from_num(-2.5) {
-            self.roll_offset += I22F10::PI*2;
+        // the gyro y axis turns the other way than the pitch angle
+        let lsb_per_rad = I22F10::from_num(GYRO_LSB_PER_RAD);
+        self.raw_data.rates = YawPitchRoll {
+            yaw: I22F10::from_num(speed.z) / lsb_per_rad,
+            pitch: -I22F10::from_num(speed.y) / lsb_per_rad,
+            roll: I22F10::from_num(speed.x) / lsb_per_rad,
+        };
+        if self.mode != Mode::Calibration {
+            self.raw_data.rates = self.raw_data.rates.sub(&self.raw_data.rate_bias);
         }
-        else if (self.raw_data.prev_ypr.roll - self.raw_data.roll_offset) < I22F10::from_num(-2.5) &&  self.raw_data.kalman.rollphi > I22F10::from_num(2.5) {
-            self.roll_offset -= I22F10::PI*2;
+        let rates = self.raw_data.rates;
+        let prev_roll = self.raw_data.kalman.roll.angle();
+        self.raw_data.kalman.filtering(acc_x,acc_y,acc_z,rates.pitch,rates.roll,TICK_US);
+        speed_z = self.raw_data.yaw_filter.filter(speed_z);
+        // the gyro y and z axes turn the other way than the motion processor
+        let lsb_per_g = I22F10::from_num(ACCEL_LSB_PER_G);
+        self.raw_data.mahony.update([rates.roll, rates.pitch, -rates.yaw], [acc_x / lsb_per_g, acc_y / lsb_per_g, acc_z / lsb_per_g], TICK_US);
+
+        if self.raw_data.kalman_raw {
+            self.raw_data.current_ypr = YawPitchRoll{
+                yaw: speed_z,
+                pitch: -self.raw_data.kalman.pitch.angle(),
+                roll: unwrap(prev_roll, self.raw_data.kalman.roll.angle(), &mut self.raw_data.roll_offset),
+            };
+        } else {
+            let ypr = YawPitchRoll::from(self.raw_data.mahony.quaternion());
+            let prev = self.raw_data.mahony_ypr;
+            self.raw_data.mahony_ypr = ypr;
+            self.raw_data.current_ypr = YawPitchRoll{
+                yaw: unwrap(prev.yaw, ypr.yaw, &mut self.raw_data.yaw_offset),
+                pitch: -ypr.pitch,
+                roll: unwrap(prev.roll, ypr.roll, &mut self.raw_data.roll_offset),
+            };
         }
-        self.raw_data.current_ypr = YawPitchRoll{
-            yaw: speed_z,
-            pitch: -self.raw_data.kalman.pitchphi,
-            roll: self.raw_data.kalman.rollphi + self.raw_data.roll_offset,
-        };
 
         if self.mode != Mode::Calibration {
             self.raw_data.current_ypr = self.raw_data.current_ypr.sub(&self.calibration_ypr_raw);
         }
-        
+        //the attitude filters had the rates as they are, only the rate loops get them filtered
+        self.raw_data.filter_rates();
     }
-}
\ No newline at end of file
+
+    //the accelerometer reading along the vertical, tilted with the attitude of the motion processor
+    pub fn vertical_force(&self) -> I22F10 {
+        let acc = self.raw_data.accel;
+        let (pitch, roll) = (self.sensor_ypr.pitch, self.sensor_ypr.roll);
+        -I22F10::from_num(acc.x) * sin(pitch)
+            + I22F10::from_num(acc.y) * cos(pitch) * sin(roll)
+            + I22F10::from_num(acc.z) * cos(pitch) * cos(roll)
+    }
+
+    //vertical acceleration in m/s^2 with gravity taken out, up is positive
+    pub fn vertical_accel(&self) -> I22F10 {
+        let gravity = self.raw_data.gravity;
+        (self.vertical_force() - gravity) / gravity * I22F10::from_num(GRAVITY)
+    }
+}
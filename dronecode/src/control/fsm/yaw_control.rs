@@ -1,10 +1,10 @@
 use fixed::types::I22F10;
-use tudelft_quadrupel::motor::set_motors;
 use crate::control::drone::Drone;
-use crate::control::TICK_FREQ;
-use crate::control::utils::calc_motors;
+use crate::control::{TICK_FREQ, TICK_US};
+use crate::control::pid::YAW_SCALE;
+use crate::hal::DroneHal;
 
-impl Drone{
+impl<H: DroneHal> Drone<H> {
     pub fn yaw_operate(&mut self){
         self.read_sensor_ypr();
         // + Yaw +
@@ -15,9 +15,7 @@ impl Drone{
         //                                                                              \/ CHANGE IF JOYSTICK NOT / TOO SENSITIVE 
         let ref_velocity = I22F10::from_num(3) * self.js_ypr.yaw; // radians per seconds
 
-
-        // Calculate the PID value                \/ CHANGE IF P NOT BIG / SMALL ENOUGH
-        self.motor_ypr.yaw = I22F10::from_num(1) * self.yaw_pid.p * (ref_velocity - sensor_velocity);
+        self.motor_ypr.yaw = self.yaw_pid.update(ref_velocity, sensor_velocity, &YAW_SCALE, TICK_US);
 
         if self.yaw_pid.p < 1 && self.yaw_pid.d < 1 {
             self.motor_ypr.yaw = I22F10::from_num(100) * self.js_ypr.yaw;
@@ -26,7 +24,7 @@ impl Drone{
         self.motor_ypr.roll = I22F10::from_num(500) * self.js_ypr.roll;
         self.motor_ypr.pitch = I22F10::from_num(500) * self.js_ypr.pitch;
 
-        set_motors(calc_motors(self.motor_ypr, I22F10::from_num(self.js_t)));
+        self.hal.set_motors(self.mixer.motors(self.motor_ypr, I22F10::from_num(self.js_t)));
         self.prev_sensor_ypr_control = self.sensor_ypr;
     }        
 }
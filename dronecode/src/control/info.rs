@@ -1,41 +1,53 @@
 use alloc::vec::Vec;
 use fixed::types::I22F10;
-// use tudelft_quadrupel::barometer::read_pressure;
-use tudelft_quadrupel::motor::get_motors;
-use tudelft_quadrupel::uart::send_bytes;
-use share_lib::{Command, Mode, serialize_message};
+use share_lib::{Command, Mode, PROTOCOL_REVISION, Telemetry, serialize_message, serialize_message_fec};
 use crate::control::drone::Drone;
-use crate::control::yaw_pitch_roll::YawPitchRoll;
+use crate::hal::DroneHal;
 
-//send the real_time value 0f  motors
-pub fn send_motor_vals(){
-    let motor = get_motors();
-    let mut temp = Vec::new();
-    temp.extend(serialize_message(Command::Motor1 { num:motor[0] }));
-    temp.extend(serialize_message(Command::Motor2 { num:motor[1] }));
-    temp.extend(serialize_message(Command::Motor3 { num:motor[2] }));
-    temp.extend(serialize_message(Command::Motor4 { num:motor[3] }));
-    send_bytes(&temp);
+//identifies this firmware image in the handshake, a hash of the sources set by build.rs
+const BUILD_ID: u32 = match u32::from_str_radix(env!("BUILD_ID"), 16) {
+    Ok(id) => id,
+    Err(_) => panic!("BUILD_ID is not a hexadecimal number"),
+};
+
+//answer the handshake of the pc with the protocol revision, build and modes of this firmware
+pub fn send_hello_ack(hal: &mut impl DroneHal){
+    let modes = Mode::ALL.iter().fold(0, |mask, mode| mask | mode.bit());
+    hal.send_bytes(&serialize_message(Command::HelloAck { revision: PROTOCOL_REVISION, build_id: BUILD_ID, modes }));
 }
 
-//send the calibration value
-pub fn send_calibration_vals(ypr:YawPitchRoll){
-    let mut temp = Vec::new();
-    temp.extend(serialize_message(Command::Trueyaw{num: ypr.yaw.to_bits()}));
-    temp.extend(serialize_message(Command::Truepitch{num: ypr.pitch.to_bits()}));
-    temp.extend(serialize_message(Command::Trueroll{num: ypr.roll.to_bits()}));
-    send_bytes(&temp);
+//send a snapshot of the drone state in one frame, not inlined for the same reason as datalog
+#[inline(never)]
+pub fn send_telemetry<H: DroneHal>(drone: &mut Drone<H>){
+    let attitude = drone.attitude();
+    let data = Telemetry {
+        tick: drone.tick,
+        mode: drone.mode,
+        attitude: [attitude.yaw.to_bits(), attitude.pitch.to_bits(), attitude.roll.to_bits()],
+        setpoint: [drone.js_ypr.yaw.to_bits(), drone.js_ypr.pitch.to_bits(), drone.js_ypr.roll.to_bits()],
+        throttle: drone.js_t,
+        motors: drone.hal.get_motors(),
+        battery: drone.battery,
+        height: drone.height.altitude.to_bits(),
+        vertical_speed: drone.height.velocity.to_bits(),
+        saturation: [drone.mixer.saturation.thrust, drone.mixer.saturation.roll_pitch, drone.mixer.saturation.yaw],
+    };
+    if drone.link_fec {
+        drone.hal.send_bytes(&serialize_message_fec(Command::Telemetry { data }));
+    } else {
+        drone.hal.send_bytes(&serialize_message(Command::Telemetry { data }));
+    }
 }
 
 //send the configuration value for joystick
-pub fn send_configure_joystick_vals(drone: &Drone){
+pub fn send_configure_joystick_vals<H: DroneHal>(drone: &mut Drone<H>){
     if drone.mode == Mode::Manual|| drone.mode == Mode::YawControlled|| drone.mode == Mode::FullControl || drone.mode == Mode::Raw{
         let mut temp = Vec::new();
         temp.extend(serialize_message(Command::YawBack { num: I22F10::from_num(drone.js_ypr.yaw).to_bits() }));
         temp.extend(serialize_message(Command::PitchBack { num: I22F10::from_num(drone.js_ypr.pitch).to_bits() }));
         temp.extend(serialize_message(Command::RollBack { num: I22F10::from_num(drone.js_ypr.roll).to_bits() }));
         temp.extend(serialize_message(Command::ThrottleBack { num: drone.js_t }));
-        send_bytes(&temp);
+        drone.hal.send_bytes(&temp);
     }
 }
 
@@ -0,0 +1,453 @@
+use cordic::{cos, sin};
+use fixed::types::{I16F16, I22F10, I8F24};
+use super::yaw_pitch_roll::YawPitchRoll;
+
+//the frame the motors are mounted on, every motor sits on an arm of the same length
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Frame {
+    Plus, // motor 1 on the front, 2 on the right, 3 on the back and 4 on the left
+    X, // the plus frame turned by 45 degrees, motor 1 front left and 2 front right
+    Custom, // arms at the angles set through the parameters
+}
+
+const PLUS_ANGLES: [i32; 4] = [0, -90, 180, 90];
+const X_ANGLES: [i32; 4] = [45, -45, -135, 135];
+//the propellers of motor 1 and 3 turn clockwise, 2 and 4 counter-clockwise
+const DEFAULT_SPIN: u8 = 0b0101;
+//the speed set_motors limits the motors to
+pub const MOTOR_MAX: u16 = 1000;
+//battery voltage the gains are tuned at, 3.7V per cell
+const NOMINAL_VOLTAGE: f32 = 11.1;
+//below this voltage in V the board runs from usb and there is no pack to make up for
+const BATTERY_MIN: i32 = 5;
+//the motors are driven at most this much softer or harder than at the nominal voltage
+const COMPENSATION_MIN: f32 = 0.8;
+const COMPENSATION_MAX: f32 = 1.25;
+
+//how much the last mix had to give up to keep the motors within their limits, in percent
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Saturation {
+    pub thrust: u8, // change of the collective thrust
+    pub roll_pitch: u8, // part of the roll and pitch torque left out
+    pub yaw: u8, // part of the yaw torque left out
+}
+
+//the motor mixer: lift, roll, pitch and yaw to the speed of every motor, tunable through the parameter registry
+#[derive(Clone)]
+pub struct Mixer {
+    pub lift: I22F10, // thrust factor of lift, roll and pitch
+    pub yaw: I22F10, // drag factor of yaw
+    pub idle: I22F10, // lowest RPM of a motor while the throttle is on, the highest is MOTOR_MAX
+    pub min_throttle: I22F10, // the motors stay off above this throttle, lift is negative
+    pub nominal_voltage: I22F10, // battery voltage in V the motor commands are scaled to, 0 turns the compensation off
+    pub thrust_curve: I22F10, // 1 when the thrust goes with the square of the motor command, 0 when it goes with the command
+    voltage: I22F10, // smoothed battery voltage in V, 0 before the first reading
+    frame: Frame,
+    angles: [I22F10; 4], // direction of the arm of every motor in degrees, counter-clockwise from the front
+    spin: u8, // bit n is set when the propeller of motor n+1 turns clockwise seen from above
+    order: [u8; 4], // the output every motor is connected to, from 0
+    matrix: [[I22F10; 4]; 4], // lift, roll, pitch and yaw to the squared speed of every motor
+    pub saturation: Saturation,
+}
+
+impl Mixer {
+    pub fn new() -> Self {
+        let mut mixer = Mixer {
+            // RESTRICTED MODE (400 RPM): lift 300, yaw 2000
+            // UNRESTRICTED MODE (1000 RPM)
+            lift: I22F10::from_num(1000),
+            yaw: I22F10::from_num(7000),
+            idle: I22F10::from_num(180),
+            min_throttle: I22F10::from_num(-50),
+            nominal_voltage: I22F10::from_num(NOMINAL_VOLTAGE),
+            thrust_curve: I22F10::from_num(1),
+            voltage: I22F10::from_num(0),
+            frame: Frame::Plus,
+            angles: PLUS_ANGLES.map(I22F10::from_num),
+            spin: DEFAULT_SPIN,
+            order: [0, 1, 2, 3],
+            matrix: [[I22F10::from_num(0); 4]; 4],
+            saturation: Saturation::default(),
+        };
+        mixer.set_geometry(mixer.angles, mixer.spin);
+        mixer
+    }
+
+    pub fn frame(&self) -> Frame {
+        self.frame
+    }
+
+    pub fn angle(&self, motor: usize) -> I22F10 {
+        self.angles[motor]
+    }
+
+    pub fn spin(&self) -> u8 {
+        self.spin
+    }
+
+    pub fn matrix(&self) -> [[I22F10; 4]; 4] {
+        self.matrix
+    }
+
+    //the motor order as the digits of the outputs, 1234 drives motor 1 on output 1 and so on
+    pub fn order(&self) -> u16 {
+        self.order.iter().fold(0, |digits, &output| digits * 10 + output as u16 + 1)
+    }
+
+    //a battery reading in centivolts, smoothed as the voltage dips with every change of the current
+    pub fn set_battery(&mut self, centivolts: u16) {
+        let reading = I22F10::from_num(centivolts) / 100;
+        if self.voltage == 0 {
+            self.voltage = reading;
+        } else {
+            self.voltage += (reading - self.voltage) / 8;
+        }
+    }
+
+    //how much harder the motors are driven than at the nominal voltage, so an emptying pack gives the same thrust
+    pub fn compensation(&self) -> I22F10 {
+        if self.nominal_voltage <= 0 || self.voltage < BATTERY_MIN {
+            I22F10::from_num(1)
+        } else {
+            (self.nominal_voltage / self.voltage).clamp(I22F10::from_num(COMPENSATION_MIN), I22F10::from_num(COMPENSATION_MAX))
+        }
+    }
+
+    //share of the thrust of the full command at the nominal voltage, for a share of the full command
+    fn thrust_share(&self, command: I8F24) -> I8F24 {
+        let f = I8F24::from_num(self.thrust_curve);
+        (I8F24::from_num(1) - f) * command + f * command * command
+    }
+
+    //the inverse, the share of the full command that gives a share of the thrust
+    fn command_share(&self, thrust: I8F24) -> I8F24 {
+        let f = I8F24::from_num(self.thrust_curve);
+        if f <= 0 {
+            return thrust;
+        }
+        let linear = I8F24::from_num(1) - f;
+        ((linear * linear + 4 * f * thrust).sqrt() - linear) / (2 * f)
+    }
+
+    //plus and X move the arms to their angles, custom keeps the angles set so far
+    pub fn set_frame(&mut self, frame: Frame) -> bool {
+        let angles = match frame {
+            Frame::Plus => PLUS_ANGLES.map(I22F10::from_num),
+            Frame::X => X_ANGLES.map(I22F10::from_num),
+            Frame::Custom => self.angles,
+        };
+        if self.set_geometry(angles, self.spin) {
+            self.frame = frame;
+            true
+        } else {
+            false
+        }
+    }
+
+    //moving an arm makes the frame custom
+    pub fn set_angle(&mut self, motor: usize, angle: I22F10) -> bool {
+        if self.angles[motor] == angle {
+            return true;
+        }
+        let mut angles = self.angles;
+        angles[motor] = angle;
+        if self.set_geometry(angles, self.spin) {
+            self.frame = Frame::Custom;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn set_spin(&mut self, spin: u8) -> bool {
+        self.set_geometry(self.angles, spin)
+    }
+
+    //only a permutation of the four outputs is taken
+    pub fn set_order(&mut self, digits: u16) -> bool {
+        let mut order = [0; 4];
+        let mut rest = digits;
+        for output in order.iter_mut().rev() {
+            *output = (rest % 10) as u8;
+            rest /= 10;
+        }
+        let mut used = [false; 4];
+        for output in order.iter_mut() {
+            if *output == 0 || *output > 4 || used[*output as usize - 1] || rest != 0 {
+                return false;
+            }
+            *output -= 1;
+            used[*output as usize] = true;
+        }
+        self.order = order;
+        true
+    }
+
+    //a layout that cannot turn about every axis is refused and the previous one is kept
+    fn set_geometry(&mut self, angles: [I22F10; 4], spin: u8) -> bool {
+        match mixing_matrix(angles, spin) {
+            Some(matrix) => {
+                self.angles = angles;
+                self.spin = spin;
+                self.matrix = matrix;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn motors(&mut self, ypr: YawPitchRoll, throttle: I22F10) -> [u16; 4] {
+        self.saturation = Saturation::default();
+        if throttle > self.min_throttle {
+            return [0, 0, 0, 0];
+        }
+
+        // while the throttle is on every motor is set between the idle RPM and the maximum,
+        // the mix is done on the squared speeds at the nominal voltage, which go with the thrust
+        let compensation = self.compensation();
+        let max = I22F10::from_num(MOTOR_MAX);
+        let full = max * max;
+        let share = |command: I22F10| I8F24::from_num(I16F16::from_num(command) / I16F16::from_num(max) / I16F16::from_num(compensation));
+        let low = part_of(full, self.thrust_share(share(self.idle)));
+        let high = part_of(full, self.thrust_share(share(max)));
+        let band = high - low;
+        // saturating, as a value beyond the range is far above the limits anyway
+        let thrust = self.matrix.map(|row| (row[0] * throttle).saturating_mul(self.lift));
+        let mut torque = self.matrix.map(|row| (row[1] * ypr.roll + row[2] * ypr.pitch).saturating_mul(self.lift));
+        let yaw = self.matrix.map(|row| (row[3] * ypr.yaw).saturating_mul(self.yaw));
+
+        // roll and pitch that do not fit between the limits even without thrust are scaled down,
+        // the last resort as it is what keeps the drone upright
+        let roll_pitch = spread(torque);
+        if roll_pitch > band {
+            let scale = band / roll_pitch;
+            torque = torque.map(|t| t * scale);
+            self.saturation.roll_pitch = percent(I22F10::from_num(1) - scale, I22F10::from_num(1));
+        }
+        let mut mix = add(thrust, torque);
+        let mut collective = fit(mix, low, high);
+        mix = shift(mix, collective);
+
+        // yaw is added as far as the collective thrust can move out of its way, the rest is taken off the yaw
+        // rounding can leave the scaled roll and pitch a hair wider than the band
+        let room = band.max(spread(mix));
+        let mut yaw_scale = I22F10::from_num(1);
+        if spread(add(mix, yaw)) > room {
+            let mut fits = I22F10::from_num(0);
+            let mut step = I22F10::from_num(0.5);
+            while step > I22F10::DELTA {
+                if spread(add(mix, yaw.map(|y| y * (fits + step)))) <= room {
+                    fits += step;
+                }
+                step /= 2;
+            }
+            yaw_scale = fits;
+            self.saturation.yaw = percent(I22F10::from_num(1) - yaw_scale, I22F10::from_num(1));
+        }
+        mix = add(mix, yaw.map(|y| y * yaw_scale));
+        let second = fit(mix, low, high);
+        collective = collective.saturating_add(second);
+        mix = shift(mix, second);
+        let requested = thrust.iter().fold(I22F10::from_num(0), |sum, &t| sum.saturating_add(t / 4));
+        self.saturation.thrust = percent(collective.abs(), requested);
+
+        let mut motors = [0; 4];
+        for (&squared, &output) in mix.iter().zip(self.order.iter()) {
+            // rounding can leave a motor a hair outside the limits
+            let squared = squared.clamp(low, high);
+            // with the thrust going with the square of the command the square root is exact
+            let speed = if self.thrust_curve >= 1 {
+                squared.sqrt()
+            } else {
+                part_of(max, self.command_share(share_of(squared, full)))
+            };
+            motors[output as usize] = (speed * compensation).clamp(self.idle, max).to_num::<u16>();
+        }
+        motors
+    }
+}
+
+impl Default for Mixer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+//a share of a value, kept to 24 fractional bits until the end
+fn part_of(value: I22F10, share: I8F24) -> I22F10 {
+    I22F10::from_bits(((value.to_bits() as i64 * share.to_bits() as i64) >> 24) as i32)
+}
+
+fn share_of(part: I22F10, whole: I22F10) -> I8F24 {
+    I8F24::from_bits((((part.to_bits() as i64) << 24) / whole.to_bits() as i64) as i32)
+}
+
+fn add(a: [I22F10; 4], b: [I22F10; 4]) -> [I22F10; 4] {
+    [0, 1, 2, 3].map(|i| a[i].saturating_add(b[i]))
+}
+
+fn shift(values: [I22F10; 4], by: I22F10) -> [I22F10; 4] {
+    values.map(|v| v.saturating_add(by))
+}
+
+fn spread(values: [I22F10; 4]) -> I22F10 {
+    let max = values.iter().copied().max().unwrap_or_default();
+    let min = values.iter().copied().min().unwrap_or_default();
+    max.saturating_sub(min)
+}
+
+//the collective change that brings all values between the limits, as far as their spread allows
+fn fit(values: [I22F10; 4], low: I22F10, high: I22F10) -> I22F10 {
+    let max = values.iter().copied().max().unwrap_or_default();
+    let min = values.iter().copied().min().unwrap_or_default();
+    if max > high {
+        high.saturating_sub(max)
+    } else if min < low {
+        low.saturating_sub(min)
+    } else {
+        I22F10::from_num(0)
+    }
+}
+
+fn percent(part: I22F10, whole: I22F10) -> u8 {
+    if whole <= 0 || part <= 0 {
+        0
+    } else if part >= whole {
+        100
+    } else {
+        (part / whole * 100).to_num()
+    }
+}
+
+///The squared speed u of the motors gives, per unit of the lift and yaw factors,
+///lift:  -sum(u)             (the throttle is negative for lift)
+///roll:   sum(y*u)           with the arm at x = cos(angle) forward and y = sin(angle) left
+///pitch: -sum(x*u)
+///yaw:   -sum(s*u)           with s = 1 for a propeller turning clockwise and -1 otherwise
+///the mixing matrix is the inverse of this allocation matrix, which for the plus frame gives
+///-1/4 on lift, 1/2 on the arms along the axis of roll and pitch and 1/4 on yaw
+fn mixing_matrix(angles: [I22F10; 4], spin: u8) -> Option<[[I22F10; 4]; 4]> {
+    let mut allocation = [[0.0f32; 4]; 4];
+    for (motor, angle) in angles.iter().enumerate() {
+        let radians = I8F24::PI * I8F24::from_num(I16F16::from_num(*angle) / 180);
+        let s = if spin & (1 << motor) != 0 { 1.0 } else { -1.0 };
+        allocation[0][motor] = -1.0;
+        allocation[1][motor] = sin(radians).to_num::<f32>();
+        allocation[2][motor] = -cos(radians).to_num::<f32>();
+        allocation[3][motor] = -s;
+    }
+    let inverse = invert(allocation)?;
+    Some(inverse.map(|row| row.map(I22F10::from_num)))
+}
+
+//Gauss-Jordan elimination with partial pivoting, None when the matrix is singular
+fn invert(mut a: [[f32; 4]; 4]) -> Option<[[f32; 4]; 4]> {
+    let mut inverse = [[0.0f32; 4]; 4];
+    for (i, row) in inverse.iter_mut().enumerate() {
+        row[i] = 1.0;
+    }
+    for column in 0..4 {
+        let pivot = (column..4).max_by(|&i, &j| a[i][column].abs().total_cmp(&a[j][column].abs()))?;
+        if a[pivot][column].abs() < 1e-3 {
+            return None;
+        }
+        a.swap(column, pivot);
+        inverse.swap(column, pivot);
+        let scale = a[column][column];
+        for k in 0..4 {
+            a[column][k] /= scale;
+            inverse[column][k] /= scale;
+        }
+        for row in 0..4 {
+            if row != column {
+                let factor = a[row][column];
+                for k in 0..4 {
+                    a[row][k] -= factor * a[column][k];
+                    inverse[row][k] -= factor * inverse[column][k];
+                }
+            }
+        }
+    }
+    // the rows of the inverse are the motors, the columns lift, roll, pitch and yaw
+    Some(inverse)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THROTTLE: i32 = -400;
+
+    fn torque(yaw: i32, pitch: i32, roll: i32) -> YawPitchRoll {
+        YawPitchRoll { yaw: I22F10::from_num(yaw), pitch: I22F10::from_num(pitch), roll: I22F10::from_num(roll) }
+    }
+
+    //which motors speed up (1) and slow down (-1) for a torque, against the same throttle without it
+    fn signs(mixer: &mut Mixer, ypr: YawPitchRoll) -> [i8; 4] {
+        let hover = mixer.motors(torque(0, 0, 0), I22F10::from_num(THROTTLE));
+        let motors = mixer.motors(ypr, I22F10::from_num(THROTTLE));
+        [0, 1, 2, 3].map(|i| match motors[i] as i32 - hover[i] as i32 {
+            d if d > 1 => 1,
+            d if d < -1 => -1,
+            _ => 0,
+        })
+    }
+
+    #[test]
+    fn plus_frame() {
+        let mut mixer = Mixer::new();
+        assert_eq!(mixer.frame(), Frame::Plus);
+        // roll speeds up the left arm, pitch the back arm
+        assert_eq!(signs(&mut mixer, torque(0, 0, 100)), [0, -1, 0, 1]);
+        assert_eq!(signs(&mut mixer, torque(0, 100, 0)), [-1, 0, 1, 0]);
+        // yaw speeds up the counter-clockwise propellers
+        assert_eq!(signs(&mut mixer, torque(10, 0, 0)), [-1, 1, -1, 1]);
+    }
+
+    #[test]
+    fn x_frame() {
+        let mut mixer = Mixer::new();
+        assert!(mixer.set_frame(Frame::X));
+        assert_eq!(signs(&mut mixer, torque(0, 0, 100)), [1, -1, -1, 1]);
+        assert_eq!(signs(&mut mixer, torque(0, 100, 0)), [-1, -1, 1, 1]);
+        assert_eq!(signs(&mut mixer, torque(10, 0, 0)), [-1, 1, -1, 1]);
+    }
+
+    #[test]
+    fn custom_frame() {
+        // an X frame that is longer than it is wide
+        let mut mixer = Mixer::new();
+        for (motor, angle) in [30, -30, -150, 150].into_iter().enumerate() {
+            assert!(mixer.set_angle(motor, I22F10::from_num(angle)));
+        }
+        assert_eq!(mixer.frame(), Frame::Custom);
+        assert_eq!(signs(&mut mixer, torque(0, 0, 100)), [1, -1, -1, 1]);
+        assert_eq!(signs(&mut mixer, torque(0, 100, 0)), [-1, -1, 1, 1]);
+        // two clockwise propellers on the same arm leave a torque that cannot be given, it is refused
+        assert!(!mixer.set_angle(2, I22F10::from_num(30)));
+        assert_eq!(mixer.angle(2), I22F10::from_num(-150));
+    }
+
+    #[test]
+    fn spin_and_order() {
+        let mut mixer = Mixer::new();
+        // with every propeller turning the same way there is no yaw
+        assert!(!mixer.set_spin(0));
+        assert!(mixer.set_spin(0b1010));
+        assert_eq!(signs(&mut mixer, torque(10, 0, 0)), [1, -1, 1, -1]);
+        // motor 1 on output 2, motor 2 on output 1 and so on
+        assert!(mixer.set_order(2143));
+        assert_eq!(signs(&mut mixer, torque(0, 0, 100)), [-1, 0, 1, 0]);
+        assert!(!mixer.set_order(1123));
+        assert_eq!(mixer.order(), 2143);
+    }
+
+    #[test]
+    fn off_above_the_minimum_throttle() {
+        let mut mixer = Mixer::new();
+        let above = mixer.min_throttle + I22F10::from_num(1);
+        assert_eq!(mixer.motors(torque(10, 100, 100), above), [0; 4]);
+        let below = mixer.min_throttle - I22F10::from_num(1);
+        assert!(mixer.motors(torque(0, 0, 0), below).iter().all(|&motor| I22F10::from_num(motor) >= mixer.idle));
+    }
+}
@@ -0,0 +1,158 @@
+use fixed::types::I22F10;
+use share_lib::{Command, ParamInfo, serialize_message};
+use crate::control::drone::Drone;
+use crate::control::mixer::Frame;
+use crate::hal::DroneHal;
+
+//a tunable value of the drone, reached through ParamSet, ParamGet and ParamList
+pub struct Param<H: DroneHal> {
+    pub name: &'static str,
+    pub min: I22F10,
+    pub max: I22F10,
+    pub get: fn(&Drone<H>) -> I22F10,
+    pub set: fn(&mut Drone<H>, I22F10),
+}
+
+//number of parameters, the same on every HAL, so the config layout can be checked without one
+pub const PARAM_COUNT: usize = 53;
+
+const fn int(num: i32) -> I22F10 {
+    I22F10::from_bits(num << 10)
+}
+
+impl<H: DroneHal> Drone<H> {
+    //the id of a parameter is its index, only append to keep the ids stable
+    pub const PARAMS: [Param<H>; PARAM_COUNT] = [
+        Param { name: "yaw_p", min: int(0), max: int(100), get: |d| d.yaw_pid.p, set: |d, v| d.yaw_pid.p = v },
+        Param { name: "yaw_i", min: int(0), max: int(100), get: |d| d.yaw_pid.i, set: |d, v| d.yaw_pid.i = v },
+        Param { name: "yaw_d", min: int(0), max: int(100), get: |d| d.yaw_pid.d, set: |d, v| d.yaw_pid.d = v },
+        Param { name: "pitch_p", min: int(0), max: int(100), get: |d| d.pitch_pid.p, set: |d, v| d.pitch_pid.p = v },
+        Param { name: "pitch_i", min: int(0), max: int(100), get: |d| d.pitch_pid.i, set: |d, v| d.pitch_pid.i = v },
+        Param { name: "pitch_d", min: int(0), max: int(100), get: |d| d.pitch_pid.d, set: |d, v| d.pitch_pid.d = v },
+        Param { name: "roll_p", min: int(0), max: int(100), get: |d| d.roll_pid.p, set: |d, v| d.roll_pid.p = v },
+        Param { name: "roll_i", min: int(0), max: int(100), get: |d| d.roll_pid.i, set: |d, v| d.roll_pid.i = v },
+        Param { name: "roll_d", min: int(0), max: int(100), get: |d| d.roll_pid.d, set: |d, v| d.roll_pid.d = v },
+        Param { name: "height_p", min: int(0), max: int(100), get: |d| d.height.pid.p, set: |d, v| d.height.pid.p = v },
+        Param { name: "height_i", min: int(0), max: int(100), get: |d| d.height.pid.i, set: |d, v| d.height.pid.i = v },
+        Param { name: "height_d", min: int(0), max: int(100), get: |d| d.height.pid.d, set: |d, v| d.height.pid.d = v },
+        // cut-off frequencies stay below half the 100Hz sampling frequency
+        Param { name: "height_cutoff", min: int(1), max: int(49),
+            get: |d| d.height.baro_filter.cutoff_freq, set: |d, v| d.height.baro_filter.set_cutoff(v) },
+        Param { name: "raw_cutoff", min: int(1), max: int(49),
+            get: |d| d.raw_data.yaw_filter.cutoff_freq, set: |d, v| d.raw_data.yaw_filter.set_cutoff(v) },
+        // noise of the kalman filters in thousandths, see filters::kalman
+        Param { name: "kalman_pitch_q", min: int(0), max: int(100),
+            get: |d| d.raw_data.kalman.pitch.q_angle, set: |d, v| d.raw_data.kalman.pitch.q_angle = v },
+        Param { name: "kalman_pitch_r", min: int(1), max: int(100),
+            get: |d| d.raw_data.kalman.pitch.r, set: |d, v| d.raw_data.kalman.pitch.r = v },
+        Param { name: "kalman_roll_q", min: int(0), max: int(100),
+            get: |d| d.raw_data.kalman.roll.q_angle, set: |d, v| d.raw_data.kalman.roll.q_angle = v },
+        Param { name: "kalman_roll_r", min: int(1), max: int(100),
+            get: |d| d.raw_data.kalman.roll.r, set: |d, v| d.raw_data.kalman.roll.r = v },
+        Param { name: "mixer_lift", min: int(0), max: int(5000), get: |d| d.mixer.lift, set: |d, v| d.mixer.lift = v },
+        Param { name: "mixer_yaw", min: int(0), max: int(20000), get: |d| d.mixer.yaw, set: |d, v| d.mixer.yaw = v },
+        Param { name: "mixer_idle", min: int(0), max: int(400), get: |d| d.mixer.idle, set: |d, v| d.mixer.idle = v },
+        Param { name: "pitch_rate_p", min: int(0), max: int(100), get: |d| d.pitch_rate_pid.p, set: |d, v| d.pitch_rate_pid.p = v },
+        Param { name: "pitch_rate_i", min: int(0), max: int(100), get: |d| d.pitch_rate_pid.i, set: |d, v| d.pitch_rate_pid.i = v },
+        Param { name: "pitch_rate_d", min: int(0), max: int(100), get: |d| d.pitch_rate_pid.d, set: |d, v| d.pitch_rate_pid.d = v },
+        Param { name: "roll_rate_p", min: int(0), max: int(100), get: |d| d.roll_rate_pid.p, set: |d, v| d.roll_rate_pid.p = v },
+        Param { name: "roll_rate_i", min: int(0), max: int(100), get: |d| d.roll_rate_pid.i, set: |d, v| d.roll_rate_pid.i = v },
+        Param { name: "roll_rate_d", min: int(0), max: int(100), get: |d| d.roll_rate_pid.d, set: |d, v| d.roll_rate_pid.d = v },
+        // crossover of the height estimate in rad/s, below it the barometer is trusted over the accelerometer
+        Param { name: "vertical_crossover", min: int(1), max: int(10),
+            get: |d| d.height.vertical.crossover, set: |d, v| d.height.vertical.set_crossover(v) },
+        Param { name: "mahony_kp", min: int(0), max: int(20), get: |d| d.raw_data.mahony.kp, set: |d, v| d.raw_data.mahony.kp = v },
+        Param { name: "mahony_ki", min: int(0), max: int(5), get: |d| d.raw_data.mahony.ki, set: |d, v| d.raw_data.mahony.ki = v },
+        // switches, 0 is off and anything else is on
+        Param { name: "raw_kalman", min: int(0), max: int(1),
+            get: |d| I22F10::from_num(d.raw_data.kalman_raw as i32), set: |d, v| d.raw_data.kalman_raw = v != 0 },
+        Param { name: "mahony_for_dmp", min: int(0), max: int(1),
+            get: |d| I22F10::from_num(d.raw_data.replace_dmp as i32), set: |d, v| d.raw_data.replace_dmp = v != 0 },
+        Param { name: "kalman_pitch_bias_q", min: int(0), max: int(100),
+            get: |d| d.raw_data.kalman.pitch.q_bias, set: |d, v| d.raw_data.kalman.pitch.q_bias = v },
+        Param { name: "kalman_roll_bias_q", min: int(0), max: int(100),
+            get: |d| d.raw_data.kalman.roll.q_bias, set: |d, v| d.raw_data.kalman.roll.q_bias = v },
+        // filters of the gyro rates the rate loops fly on, a frequency of 0 turns them off
+        Param { name: "gyro_cutoff", min: int(0), max: int(49), get: |d| d.raw_data.gyro_filter.cutoff_freq,
+            set: |d, v| d.raw_data.gyro_filter.set_cutoff(v) },
+        Param { name: "gyro_order", min: int(2), max: int(4), get: |d| I22F10::from_num(d.raw_data.gyro_filter.order),
+            set: |d, v| d.raw_data.gyro_filter.set_order(v.to_num()) },
+        Param { name: "gyro_notch", min: int(0), max: int(49), get: |d| d.raw_data.gyro_notch.cutoff_freq,
+            set: |d, v| d.raw_data.gyro_notch.set_cutoff(v) },
+        Param { name: "d_cutoff", min: int(1), max: int(49), get: |d| d.yaw_pid.d_cutoff, set: |d, v| d.set_d_cutoff(v) },
+        // frame of the mixer, 0 is plus, 1 is X and 2 custom, see control::mixer.
+        // a layout that cannot turn about every axis is refused and the value stays
+        Param { name: "mixer_frame", min: int(0), max: int(2), get: |d| I22F10::from_num(d.mixer.frame() as i32),
+            set: |d, v| { d.mixer.set_frame(match v.to_num::<i32>() { 0 => Frame::Plus, 1 => Frame::X, _ => Frame::Custom }); } },
+        // bit n is set when the propeller of motor n+1 turns clockwise
+        Param { name: "motor_spin", min: int(0), max: int(15), get: |d| I22F10::from_num(d.mixer.spin()),
+            set: |d, v| { d.mixer.set_spin(v.to_num()); } },
+        // arm directions in degrees counter-clockwise from the front, setting one makes the frame custom
+        Param { name: "motor1_angle", min: int(-180), max: int(180), get: |d| d.mixer.angle(0), set: |d, v| { d.mixer.set_angle(0, v); } },
+        Param { name: "motor2_angle", min: int(-180), max: int(180), get: |d| d.mixer.angle(1), set: |d, v| { d.mixer.set_angle(1, v); } },
+        Param { name: "motor3_angle", min: int(-180), max: int(180), get: |d| d.mixer.angle(2), set: |d, v| { d.mixer.set_angle(2, v); } },
+        Param { name: "motor4_angle", min: int(-180), max: int(180), get: |d| d.mixer.angle(3), set: |d, v| { d.mixer.set_angle(3, v); } },
+        // the outputs of motor 1 to 4 as digits, 2143 swaps the first two and the last two
+        Param { name: "motor_order", min: int(1234), max: int(4321), get: |d| I22F10::from_num(d.mixer.order()),
+            set: |d, v| { d.mixer.set_order(v.to_num()); } },
+        // the motors stay off above this throttle, lift is negative
+        Param { name: "mixer_min_throttle", min: int(-500), max: int(0),
+            get: |d| d.mixer.min_throttle, set: |d, v| d.mixer.min_throttle = v },
+        // battery voltage in V the gains hold at, the motors are driven harder as the pack empties, 0 is off
+        Param { name: "battery_nominal", min: int(0), max: int(13),
+            get: |d| d.mixer.nominal_voltage, set: |d, v| d.mixer.nominal_voltage = v },
+        // 1 when the thrust goes with the square of the motor command, 0 when it goes with the command
+        Param { name: "thrust_curve", min: int(0), max: int(1), get: |d| d.mixer.thrust_curve, set: |d, v| d.mixer.thrust_curve = v },
+        // descent of panic mode: the share of the throttle in percent held for a time in s, then ramped to off
+        Param { name: "panic_throttle", min: int(0), max: int(100), get: |d| d.descent.throttle, set: |d, v| d.descent.throttle = v },
+        Param { name: "panic_hold", min: int(0), max: int(10), get: |d| d.descent.hold, set: |d, v| d.descent.hold = v },
+        Param { name: "panic_ramp", min: int(0), max: int(10), get: |d| d.descent.ramp, set: |d, v| d.descent.ramp = v },
+        Param { name: "panic_attitude", min: int(0), max: int(1),
+            get: |d| I22F10::from_num(d.descent.stay_level as i32), set: |d, v| d.descent.stay_level = v != 0 },
+        // LDPC encoded datalog records survive bit errors in flash, but half as many fit
+        Param { name: "log_fec", min: int(0), max: int(1),
+            get: |d| I22F10::from_num(d.log_fec as i32), set: |d, v| d.log_fec = v != 0 },
+    ];
+
+    //the derivative filters of all controllers share one cut-off
+    fn set_d_cutoff(&mut self, cutoff: I22F10) {
+        for pid in [&mut self.yaw_pid, &mut self.pitch_pid, &mut self.roll_pid,
+                    &mut self.pitch_rate_pid, &mut self.roll_rate_pid, &mut self.height.pid] {
+            pid.set_d_cutoff(cutoff);
+        }
+    }
+
+    //set a parameter within its bounds and send the value it ended up with,
+    //returns false for an unknown id
+    pub fn set_param(&mut self, id: u8, value: I22F10) -> bool {
+        match Self::PARAMS.get(id as usize) {
+            Some(param) => {
+                (param.set)(self, value.clamp(param.min, param.max));
+                self.send_param(id)
+            }
+            None => false,
+        }
+    }
+
+    //send the current value of a parameter, returns false for an unknown id
+    pub fn send_param(&mut self, id: u8) -> bool {
+        match Self::PARAMS.get(id as usize) {
+            Some(param) => {
+                let value = (param.get)(self);
+                self.hal.send_bytes(&serialize_message(Command::ParamValue { id, value }));
+                true
+            }
+            None => false,
+        }
+    }
+
+    //while a ParamList is being answered, send the description of one parameter per tick
+    pub fn send_next_param_info(&mut self) {
+        if let Some(id) = self.param_cursor {
+            let param = &Self::PARAMS[id as usize];
+            let info = ParamInfo::new(id, param.name, (param.get)(self), param.min, param.max);
+            self.hal.send_bytes(&serialize_message(Command::ParamInfo { info }));
+            self.param_cursor = if (id as usize) + 1 < Self::PARAMS.len() { Some(id + 1) } else { None };
+        }
+    }
+}
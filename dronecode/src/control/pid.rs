@@ -1,39 +1,144 @@
 use fixed::types::I22F10;
+use crate::control::TICK_FREQ;
+
+//default cut-off of the derivative filter in Hz, the controllers run at the tick frequency
+const D_CUTOFF: i32 = 10;
+
+const fn frac(num: i32, den: i32) -> I22F10 {
+    I22F10::from_bits((num << 10) / den)
+}
+
+//the gains set from the pc are multiplied by the scale of the loop they are used in,
+//which keeps them in the same range for every loop
+pub struct PidScale {
+    pub p: I22F10,
+    pub i: I22F10, // per second
+    pub d: I22F10, // per second
+}
+
+//pitch and roll angle to the rate setpoint of the inner loop
+pub const ANGLE_SCALE: PidScale = PidScale { p: frac(1, 1), i: frac(1, 1), d: frac(1, 10) };
+//pitch and roll rate to the mixer
+pub const RATE_SCALE: PidScale = PidScale { p: frac(10, 1), i: frac(10, 1), d: frac(1, 10) };
+//yaw rate
+pub const YAW_SCALE: PidScale = PidScale { p: frac(1, 1), i: frac(1, 1), d: frac(1, 100) };
+//altitude in m to lift on top of the hover lift
+pub const HEIGHT_SCALE: PidScale = PidScale { p: frac(10, 1), i: frac(10, 1), d: frac(10, 1) };
+
+//output limits, a bit more than the joystick reaches in manual mode
+pub const ATTITUDE_LIMIT: I22F10 = frac(250, 1);
+//fastest pitch and roll rate the angle loops ask for, in rad/s
+pub const RATE_LIMIT: I22F10 = frac(3, 1);
+pub const YAW_LIMIT: I22F10 = frac(100, 1);
+//how far the height controller moves the lift away from the hover lift
+pub const HEIGHT_LIMIT: I22F10 = frac(500, 1);
+
+//first order low-pass of the derivative at the tick frequency fs, with w = 2*pi*fc:
+//d = d + w/(w + fs) * (sample - d)
+fn d_share(cutoff: I22F10) -> I22F10 {
+    let w = 2.0 * core::f32::consts::PI * cutoff.to_num::<f32>();
+    I22F10::from_num(w / (w + TICK_FREQ as f32))
+}
+
+//PID controller with a derivative on the measurement, so a step of the reference does not kick,
+//and an integral that stops growing while the output is saturated
 pub struct PID{
     pub p:I22F10,
     pub i:I22F10,
-    pub d:I22F10
+    pub d:I22F10,
+    pub limit: I22F10, // the output stays within -limit..limit
+    integral: I22F10, // integral term, already multiplied by its gain
+    d_filtered: I22F10, // low-pass filtered rate of change of the measurement
+    pub d_cutoff: I22F10, // cut-off of the derivative low-pass in Hz
+    d_share: I22F10, // share of a new derivative sample in the filtered one
+    prev_measurement: Option<I22F10>,
+    output: I22F10,
 }
+
 impl PID {
-    pub fn new()->Self{
+    pub fn new(limit: I22F10)->Self{
         PID{
             p: I22F10::from_num(0),
             i: I22F10::from_num(0),
             d: I22F10::from_num(0),
+            limit,
+            integral: I22F10::from_num(0),
+            d_filtered: I22F10::from_num(0),
+            d_cutoff: I22F10::from_num(D_CUTOFF),
+            d_share: d_share(I22F10::from_num(D_CUTOFF)),
+            prev_measurement: None,
+            output: I22F10::from_num(0),
         }
     }
-}
 
-impl PID {
-    #[allow(unused)]
-    pub fn clac_pid(&mut self,last_error:I22F10,val_ref:I22F10,val_act:I22F10,
-                    dt:I22F10,p_scale:I22F10,d_scale:I22F10) -> (I22F10,I22F10) {
-        //calculate the current error
-        let error =  val_ref - val_act;
-        //the feedback of P controller
-        let p_gain = self.p * p_scale * error;
-        //the feedback of D controller
-        let d_gian = self.d * d_scale *(error - last_error)/dt;
-        //get the yaw_speed after the PID control
-        let mut calc_yaw = p_gain + d_gian;
-        if self.p < 1{
-            calc_yaw = I22F10::from_num(1500) * val_ref;
+    pub fn set_d_cutoff(&mut self, cutoff: I22F10) {
+        self.d_cutoff = cutoff;
+        self.d_share = d_share(cutoff);
+    }
+
+    pub fn with_gains(p: I22F10, i: I22F10, d: I22F10, limit: I22F10) -> Self {
+        PID { p, i, d, ..Self::new(limit) }
+    }
+
+    //the inner pitch and roll rate loops start out flyable, the angle loops are tuned on top of them
+    pub fn rate_loop() -> Self {
+        Self::with_gains(I22F10::from_num(5), I22F10::from_num(0), I22F10::from_num(0), ATTITUDE_LIMIT)
+    }
+
+    //forget the integral and the derivative history, done when a mode is entered
+    pub fn reset(&mut self) {
+        self.integral = I22F10::from_num(0);
+        self.d_filtered = I22F10::from_num(0);
+        self.prev_measurement = None;
+        self.output = I22F10::from_num(0);
+    }
+
+    //the integral term, what the controller learned about a constant offset
+    pub fn integral(&self) -> I22F10 {
+        self.integral
+    }
+
+    //one step of the controller, dt_us is the time since the previous step
+    pub fn update(&mut self, reference: I22F10, measurement: I22F10, scale: &PidScale, dt_us: u32) -> I22F10 {
+        //a step of no time has no derivative or integral, keep the last output
+        if dt_us == 0 {
+            return self.output;
         }
-        //return cal_value and error
-        (calc_yaw, error)
+        let rate = I22F10::from_num(1_000_000 / dt_us); // steps per second
+
+        //derivative on the measurement, nothing to compare to on the first step
+        if let Some(prev) = self.prev_measurement {
+            let d_raw = (prev - measurement) * rate;
+            self.d_filtered += self.d_share * (d_raw - self.d_filtered);
+        }
+        self.prev_measurement = Some(measurement);
+        self.step(reference - measurement, self.d_filtered, scale, rate)
     }
-}
 
+    //one step for a measurement whose rate of change is known, like the vertical speed
+    //of the height estimate, which is cleaner than differentiating the measurement
+    pub fn update_with_rate(&mut self, reference: I22F10, measurement: I22F10, measurement_rate: I22F10, scale: &PidScale, dt_us: u32) -> I22F10 {
+        if dt_us == 0 {
+            return self.output;
+        }
+        let rate = I22F10::from_num(1_000_000 / dt_us);
+        self.step(reference - measurement, -measurement_rate, scale, rate)
+    }
 
+    //the controller itself, d_input is the rate at which the error changes through the measurement
+    fn step(&mut self, error: I22F10, d_input: I22F10, scale: &PidScale, rate: I22F10) -> I22F10 {
+        let p_term = self.p * scale.p * error;
+        let d_term = self.d * scale.d * d_input;
 
+        //clamping anti-windup: only integrate if that does not push the output further into saturation
+        let integral = (self.integral + self.i * scale.i * error / rate).clamp(-self.limit, self.limit);
+        let unsaturated = p_term + integral + d_term;
+        let winding_up = (unsaturated > self.limit && error > 0) || (unsaturated < -self.limit && error < 0);
+        if !winding_up {
+            self.integral = integral;
+        }
 
+        self.output = (p_term + self.integral + d_term).clamp(-self.limit, self.limit);
+        self.output
+    }
+}
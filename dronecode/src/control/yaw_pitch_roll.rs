@@ -1,4 +1,4 @@
-use tudelft_quadrupel::mpu::structs::Quaternion;
+use crate::hal::Quaternion;
 use fixed::types::I22F10;
 use cordic::atan2;
 /// This struct holds the yaw, pitch, and roll that the drone things it is in.
@@ -20,6 +20,12 @@ impl YawPitchRoll {
     }
 }
 
+impl Default for YawPitchRoll {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl From<Quaternion> for YawPitchRoll {
     /// Creates a YawPitchRoll from a Quaternion
     fn from(q: Quaternion) -> Self {
@@ -0,0 +1,213 @@
+use core::f32::consts::FRAC_1_SQRT_2;
+use cordic::{cos, sin};
+use fixed::types::{I16F16, I22F10, I8F24};
+//IIR filters built from second order sections, the coefficients are kept with 24 fractional bits
+//and the signal with 16
+
+//quality factors of the sections of a butterworth filter
+const Q_ORDER_2: f32 = FRAC_1_SQRT_2; // 1/(2*cos(pi/4))
+const Q_ORDER_4: [f32; 2] = [0.541_196_1, 1.306_563]; // 1/(2*cos(pi/8)), 1/(2*cos(3*pi/8))
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Response {
+    LowPass,
+    HighPass,
+    Notch,
+}
+
+///One second order section in direct form I
+///y[n] = b0*x[n] + b1*x[n-1] + b2*x[n-2] - a1*y[n-1] - a2*y[n-2]
+///the products are summed in 64 bits and the part of the sum below the resolution of the output
+///is carried into the next one, so the rounding does not add up to a gain error at a low cut-off.
+///The section only holds the coefficients, the signals it filters keep their own SectionState
+#[derive(Debug, Copy, Clone)]
+pub struct Biquad{
+    b0: I8F24,
+    b1: I8F24,
+    b2: I8F24,
+    a1: I8F24,
+    a2: I8F24,
+}
+
+//what a section remembers of one signal
+#[derive(Debug, Copy, Clone, Default)]
+pub struct SectionState{
+    x: [I16F16; 2], // the previous two inputs
+    y: [I16F16; 2], // the previous two outputs
+    remainder: i32, // what the last output left out of the sum, below 1 << 24
+}
+
+impl Biquad {
+    //a section that passes the signal unchanged
+    pub fn new() -> Self {
+        let mut biquad = Biquad{
+            b0: I8F24::from_num(0),
+            b1: I8F24::from_num(0),
+            b2: I8F24::from_num(0),
+            a1: I8F24::from_num(0),
+            a2: I8F24::from_num(0),
+        };
+        biquad.set_pass();
+        biquad
+    }
+
+    ///Coefficients from the bilinear transform with the cut-off prewarped,
+    ///k = tan(pi*fc/fs), norm = 1/(1 + k/q + k^2)
+    ///a1 = 2*(k^2 - 1)*norm, a2 = (1 - k/q + k^2)*norm
+    ///low-pass: b0 = k^2*norm, b1 = 2*b0, b2 = b0
+    ///high-pass: b0 = norm, b1 = -2*b0, b2 = b0
+    ///notch: b0 = (1 + k^2)*norm, b1 = a1, b2 = b0, with q the centre frequency over the width
+    fn set_coefficients(&mut self, response: Response, k: I8F24, q: I8F24) {
+        let one = I8F24::from_num(1);
+        let k2 = k * k;
+        let k_q = k / q;
+        let norm = one / (one + k_q + k2);
+        self.a1 = 2 * (k2 - one) * norm;
+        self.a2 = (one - k_q + k2) * norm;
+        match response {
+            Response::LowPass => {
+                self.b0 = k2 * norm;
+                self.b1 = 2 * self.b0;
+                self.b2 = self.b0;
+            }
+            Response::HighPass => {
+                self.b0 = norm;
+                self.b1 = -2 * self.b0;
+                self.b2 = self.b0;
+            }
+            Response::Notch => {
+                self.b0 = (one + k2) * norm;
+                self.b1 = self.a1;
+                self.b2 = self.b0;
+            }
+        }
+    }
+
+    //pass the signal unchanged, the state is kept
+    fn set_pass(&mut self) {
+        self.b0 = I8F24::from_num(1);
+        self.b1 = I8F24::from_num(0);
+        self.b2 = I8F24::from_num(0);
+        self.a1 = I8F24::from_num(0);
+        self.a2 = I8F24::from_num(0);
+    }
+
+    pub fn filter(&self, state: &mut SectionState, input: I16F16) -> I16F16 {
+        let product = |coefficient: I8F24, signal: I16F16| coefficient.to_bits() as i64 * signal.to_bits() as i64;
+        // the sum has 24 + 16 fractional bits
+        let sum = product(self.b0, input) + product(self.b1, state.x[0]) + product(self.b2, state.x[1])
+            - product(self.a1, state.y[0]) - product(self.a2, state.y[1])
+            + state.remainder as i64;
+        let output = sum >> 24;
+        state.remainder = (sum - (output << 24)) as i32;
+        let output = I16F16::from_bits(output as i32);
+        state.x = [input, state.x[0]];
+        state.y = [output, state.y[0]];
+        output
+    }
+}
+
+impl Default for Biquad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+//a 2nd or 4th order filter, a cut-off of 0 turns it off and the signal passes unchanged.
+//Only the 4th order needs a second section, and signals filtered alike, like the axes of the gyro,
+//share the coefficients: SECTIONS is 2 for a filter whose order can be 4, CHANNELS the number of signals
+pub struct Iir<const SECTIONS: usize = 1, const CHANNELS: usize = 1>{
+    pub response: Response,
+    pub order: u8, // 2 or 4, a notch is always 2
+    pub cutoff_freq: I22F10, // cut-off or centre frequency in Hz
+    pub q: I22F10, // centre frequency over the width of a notch
+    pub sampling_freq: I22F10,
+    sections: [Biquad; SECTIONS],
+    state: [[SectionState; SECTIONS]; CHANNELS],
+}
+
+impl<const SECTIONS: usize, const CHANNELS: usize> Iir<SECTIONS, CHANNELS> {
+    pub fn new(response: Response, order: u8, cutoff_freq: I22F10, sampling_freq: I22F10) -> Self {
+        let mut iir = Iir{
+            response,
+            order,
+            cutoff_freq,
+            q: I22F10::from_num(Q_ORDER_2),
+            sampling_freq,
+            sections: [Biquad::new(); SECTIONS],
+            state: [[SectionState::default(); SECTIONS]; CHANNELS],
+        };
+        iir.calculate_coefficients();
+        iir
+    }
+
+    pub fn low_pass(order: u8, cutoff_freq: I22F10, sampling_freq: I22F10) -> Self {
+        Self::new(Response::LowPass, order, cutoff_freq, sampling_freq)
+    }
+
+    pub fn high_pass(order: u8, cutoff_freq: I22F10, sampling_freq: I22F10) -> Self {
+        Self::new(Response::HighPass, order, cutoff_freq, sampling_freq)
+    }
+
+    pub fn notch(centre_freq: I22F10, q: I22F10, sampling_freq: I22F10) -> Self {
+        let mut iir = Self::new(Response::Notch, 2, centre_freq, sampling_freq);
+        iir.set_q(q);
+        iir
+    }
+
+    //change the cut-off frequency, the filter state is kept
+    pub fn set_cutoff(&mut self, cutoff_freq: I22F10) {
+        self.cutoff_freq = cutoff_freq;
+        self.calculate_coefficients();
+    }
+
+    pub fn set_order(&mut self, order: u8) {
+        self.order = order;
+        self.calculate_coefficients();
+    }
+
+    pub fn set_q(&mut self, q: I22F10) {
+        self.q = q;
+        self.calculate_coefficients();
+    }
+
+    fn calculate_coefficients(&mut self) {
+        for section in self.sections.iter_mut() {
+            section.set_pass();
+        }
+        // off, or a frequency the sampling cannot represent
+        if self.cutoff_freq <= 0 || 2 * self.cutoff_freq >= self.sampling_freq {
+            return;
+        }
+        let ratio = I16F16::from_num(self.cutoff_freq) / I16F16::from_num(self.sampling_freq);
+        let angle = I8F24::PI * I8F24::from_num(ratio);
+        let k = sin(angle) / cos(angle);
+        match (self.response, self.order) {
+            (Response::Notch, _) => self.sections[0].set_coefficients(Response::Notch, k, I8F24::from_num(self.q)),
+            (response, 4) if SECTIONS >= 2 => {
+                self.sections[0].set_coefficients(response, k, I8F24::from_num(Q_ORDER_4[0]));
+                self.sections[1].set_coefficients(response, k, I8F24::from_num(Q_ORDER_4[1]));
+            }
+            (response, _) => self.sections[0].set_coefficients(response, k, I8F24::from_num(Q_ORDER_2)),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.state = [[SectionState::default(); SECTIONS]; CHANNELS];
+    }
+
+    //filter the next sample of one of the signals
+    pub fn filter_channel(&mut self, channel: usize, input: I22F10) -> I22F10 {
+        let mut signal = I16F16::from_num(input);
+        for (section, state) in self.sections.iter().zip(self.state[channel].iter_mut()) {
+            signal = section.filter(state, signal);
+        }
+        I22F10::from_num(signal)
+    }
+}
+
+impl<const SECTIONS: usize> Iir<SECTIONS, 1> {
+    pub fn filter(&mut self, input: I22F10) -> I22F10 {
+        self.filter_channel(0, input)
+    }
+}
@@ -1,60 +1,142 @@
 use cordic::{atan2};
-use fixed::types::I22F10;
-const DEG2RAD:f32 = 0.017; //ref value for degree to radian
+use fixed::types::{I22F10, I8F24};
+//the covariances are small numbers, they are kept with 24 fractional bits.
+//Q and R are set in thousandths, so they can be tuned through the I22F10 parameters
+const Q_ANGLE: i32 = 1; //default angle process noise in 0.001 rad^2/s
+const Q_BIAS: i32 = 3; //default bias process noise in 0.001 (rad/s)^2/s
+const R_ANGLE: i32 = 30; //default accelerometer angle noise in 0.001 rad^2
+
+fn thousandths(value: I22F10) -> I8F24 {
+    I8F24::from_num(value) / 1000
+}
+
+//keep an angle between -pi and pi
+fn wrap(angle: I8F24) -> I8F24 {
+    if angle > I8F24::PI {
+        angle - 2 * I8F24::PI
+    } else if angle < -I8F24::PI {
+        angle + 2 * I8F24::PI
+    } else {
+        angle
+    }
+}
+
+pub struct KalmanAxis{
+    angle: I8F24, // the angle in rad
+    bias: I8F24, // the bias of the gyro in rad/s
+    p: [[I8F24; 2]; 2], // covariance of the angle and bias error
+    pub q_angle: I22F10, // process noise of the angle in 0.001 rad^2/s
+    pub q_bias: I22F10, // process noise of the bias in 0.001 (rad/s)^2/s
+    pub r: I22F10, // measurement noise of the accelerometer angle in 0.001 rad^2
+}
+
+///Kalman filter with the state x = [angle, gyro bias]
+///predict with the gyro rate w:
+///angle = angle + (w - bias)*dt
+///P = F*P*F' + Q*dt, with F = [[1, -dt], [0, 1]] and Q = diag(q_angle, q_bias)
+///update with the angle z from the accelerometer:
+///S = P00 + r, K = [P00, P10]/S
+///x = x + K*(z - angle), P = (I - K*[1, 0])*P
+impl KalmanAxis{
+    pub fn new() -> Self {
+        KalmanAxis{
+            angle: I8F24::from_num(0),
+            bias: I8F24::from_num(0),
+            p: [[I8F24::from_num(0); 2]; 2],
+            q_angle: I22F10::from_num(Q_ANGLE),
+            q_bias: I22F10::from_num(Q_BIAS),
+            r: I22F10::from_num(R_ANGLE),
+        }
+    }
+
+    pub fn angle(&self) -> I22F10 {
+        I22F10::from_num(self.angle)
+    }
+
+    pub fn bias(&self) -> I22F10 {
+        I22F10::from_num(self.bias)
+    }
+
+    //one step with the gyro rate in rad/s and the angle seen by the accelerometer in rad
+    pub fn filtering(&mut self, rate: I22F10, measurement: I22F10, dt_us: u32) {
+        let dt = I8F24::from_num(dt_us as f32 / 1_000_000.0);
+        let [[p00, p01], [p10, p11]] = self.p;
+
+        // predict
+        self.angle = wrap(self.angle + (I8F24::from_num(rate) - self.bias) * dt);
+        let p00 = p00 + dt * (dt * p11 - p01 - p10 + thousandths(self.q_angle));
+        let p01 = p01 - dt * p11;
+        let p10 = p10 - dt * p11;
+        let p11 = p11 + thousandths(self.q_bias) * dt;
+
+        // update, the error is taken the short way around the circle
+        let error = wrap(I8F24::from_num(measurement) - self.angle);
+        let s = p00 + thousandths(self.r);
+        let k0 = p00 / s;
+        let k1 = p10 / s;
+        self.angle = wrap(self.angle + k0 * error);
+        self.bias += k1 * error;
+        self.p = [
+            [p00 - k0 * p00, p01 - k0 * p01],
+            [p10 - k1 * p00, p11 - k1 * p01],
+        ];
+    }
+}
+
+impl Default for KalmanAxis {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub struct Kalman{
-    pub pitchp: I22F10, // the position of pitch
-    pub pitchb: I22F10, // the bias of pitch
-    pub pitchphi: I22F10, // the angular of pitch
-    pub pitch_c1: I22F10, // the coefficient1 of pitch
-    pub pitch_c2: I22F10, // the coefficient2 of pitch
-
-    pub rollp: I22F10, // the position of roll
-    pub rollb: I22F10, // the bias of roll
-    pub rollphi: I22F10, // the angular of roll
-    pub roll_c1: I22F10, // the coefficient1 of roll
-    pub roll_c2: I22F10, // the coefficient2 of roll
+    pub pitch: KalmanAxis,
+    pub roll: KalmanAxis,
 }
+
 impl Kalman{
     pub fn new() ->Self{
         Kalman{
-            pitchp: I22F10::from_num(0.0),
-            pitchb: I22F10::from_num(0.0),
-            pitchphi: I22F10::from_num(0.0),
-            pitch_c1: I22F10::from_num(1.0),
-            pitch_c2: I22F10::from_num(1000),
-
-            rollp: I22F10::from_num(0.0),
-            rollb: I22F10::from_num(0.0),
-            rollphi: I22F10::from_num(0.0),
-            roll_c1: I22F10::from_num(1.0),
-            roll_c2: I22F10::from_num(1000)
+            pitch: KalmanAxis::new(),
+            roll: KalmanAxis::new(),
         }
     }
-    pub fn filtering(&mut self,ax:I22F10,ay:I22F10,az:I22F10,spx:I22F10,spy:I22F10){
+
+    //accelerometer in any unit, pitch and roll rates of the gyro in rad/s
+    pub fn filtering(&mut self,ax:I22F10,ay:I22F10,az:I22F10,pitch_rate:I22F10,roll_rate:I22F10,dt_us:u32){
         // get the angular of roll and pitch from the Accelerator
         let pitchsphi = atan2(ax, az);
         let rollsphi = atan2(ay, az);
 
-        // get the velocity of pitch
-        self.pitchp = I22F10::from_num(spy)*I22F10::from_num(DEG2RAD)-self.pitchb;
-        // calculate the angular of pitch from the gyro
-        let  pitchphi= self.pitchphi+self.pitchp/I22F10::from_num(100); //dt
-        // calculate the error
-        let e = pitchphi-pitchsphi;
-        // get the angle of pitch
-        self.pitchphi -= e/self.pitch_c1;
-        // update the bias of pitch
-        self.pitchb += (e/I22F10::from_num(0.01))/self.pitch_c2;
-
-        // get the velocity of roll
-        self.rollp = I22F10::from_num(spx)*I22F10::from_num(DEG2RAD)-self.rollb;
-        // calculate the angular of roll from the gyro
-        let  rollphi= self.rollphi+self.rollp/I22F10::from_num(100); //dt
-        // calculate the error
-        let e = rollphi-rollsphi;
-        // get the angle of roll
-        self.rollphi -= e/self.roll_c1;
-        // update the bias of roll
-        self.rollb += (e/I22F10::from_num(0.01))/self.roll_c2;
+        // the kalman pitch turns the other way than the pitch rate
+        self.pitch.filtering(-pitch_rate, pitchsphi, dt_us);
+        self.roll.filtering(roll_rate, rollsphi, dt_us);
+    }
+}
+
+impl Default for Kalman {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bias_converges_on_a_level_drone() {
+        // a gyro that is off by a constant rate while the drone sits level
+        let offset = I22F10::from_num(0.05);
+        let mut kalman = Kalman::new();
+        for _ in 0..6000 {
+            kalman.filtering(I22F10::from_num(0), I22F10::from_num(0), I22F10::from_num(1), offset, offset, 10_000);
+        }
+        // the pitch axis sees the rate with the other sign
+        assert!((kalman.pitch.bias() + offset).abs() < 0.002, "pitch bias {}", kalman.pitch.bias());
+        assert!((kalman.roll.bias() - offset).abs() < 0.002, "roll bias {}", kalman.roll.bias());
+        // with the bias learned the angles do not drift away
+        assert!(kalman.pitch.angle().abs() < 0.002, "pitch {}", kalman.pitch.angle());
+        assert!(kalman.roll.angle().abs() < 0.002, "roll {}", kalman.roll.angle());
     }
 }
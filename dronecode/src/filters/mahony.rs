@@ -0,0 +1,84 @@
+use fixed::types::{I16F16, I22F10};
+use crate::hal::Quaternion;
+//attitude from the raw gyro and accelerometer, a replacement for the motion processor
+const KP: f32 = 0.5; //default proportional gain in rad/s per unit of gravity error
+const KI: f32 = 0.05; //default integral gain, learns the gyro bias
+
+pub struct Mahony{
+    pub q: [I16F16; 4], // attitude quaternion w, x, y, z, the same frame as the motion processor
+    pub gyro_bias: [I16F16; 3], // gyro bias in rad/s learned by the integral, already negated
+    pub kp: I22F10,
+    pub ki: I22F10,
+}
+
+///Mahony complementary filter on the rotation group
+///v: gravity direction in the body frame predicted by the quaternion
+///a: measured gravity direction, the normalized accelerometer reading
+///e = a x v, the rotation that turns the prediction towards the measurement
+///w = gyro + kp*e + ki*integral(e)
+///q' = 1/2 * q * (0, w)
+///all three axes are propagated together, so a turn about one axis moves the others along,
+///and the yaw is integrated into an angle instead of staying a rate
+impl Mahony{
+    pub fn new() -> Self {
+        Mahony{
+            q: [I16F16::from_num(1), I16F16::from_num(0), I16F16::from_num(0), I16F16::from_num(0)],
+            gyro_bias: [I16F16::from_num(0); 3],
+            kp: I22F10::from_num(KP),
+            ki: I22F10::from_num(KI),
+        }
+    }
+
+    //gyro in rad/s and accelerometer in g, both about and along the x, y and z axes of the motion processor
+    pub fn update(&mut self, gyro: [I22F10; 3], accel: [I22F10; 3], dt_us: u32) {
+        let dt = I16F16::from_num(dt_us as f32 / 1_000_000.0);
+        let [w, x, y, z] = self.q;
+        let mut rate = gyro.map(I16F16::from_num);
+
+        //a free fall or a bad reading has no direction to correct towards
+        let accel = accel.map(I16F16::from_num);
+        let norm = (accel[0] * accel[0] + accel[1] * accel[1] + accel[2] * accel[2]).sqrt();
+        if norm > I16F16::from_num(0.1) {
+            let a = accel.map(|v| v / norm);
+            let v = [
+                2 * (x * z - w * y),
+                2 * (w * x + y * z),
+                w * w - x * x - y * y + z * z,
+            ];
+            let e = [
+                a[1] * v[2] - a[2] * v[1],
+                a[2] * v[0] - a[0] * v[2],
+                a[0] * v[1] - a[1] * v[0],
+            ];
+            let kp = I16F16::from_num(self.kp);
+            let ki = I16F16::from_num(self.ki);
+            for i in 0..3 {
+                self.gyro_bias[i] += ki * e[i] * dt;
+                rate[i] += kp * e[i] + self.gyro_bias[i];
+            }
+        }
+
+        let half_dt = dt / 2;
+        let [gx, gy, gz] = rate.map(|r| r * half_dt);
+        let q = [
+            w - x * gx - y * gy - z * gz,
+            x + w * gx + y * gz - z * gy,
+            y + w * gy - x * gz + z * gx,
+            z + w * gz + x * gy - y * gx,
+        ];
+        let norm = (q[0] * q[0] + q[1] * q[1] + q[2] * q[2] + q[3] * q[3]).sqrt();
+        self.q = q.map(|v| v / norm);
+    }
+
+    //the attitude as the motion processor reports it
+    pub fn quaternion(&self) -> Quaternion {
+        let [w, x, y, z] = self.q.map(|v| v.to_num::<f32>());
+        Quaternion { w, x, y, z }
+    }
+}
+
+impl Default for Mahony {
+    fn default() -> Self {
+        Self::new()
+    }
+}
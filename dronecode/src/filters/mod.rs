@@ -1,2 +1,4 @@
-pub mod butterworth;
-pub mod kalman;
\ No newline at end of file
+pub mod biquad;
+pub mod kalman;
+pub mod mahony;
+pub mod vertical;
\ No newline at end of file
@@ -0,0 +1,73 @@
+use fixed::types::{I16F16, I22F10};
+//complementary filter for the height: the accelerometer gives the fast changes,
+//the barometer keeps the altitude from drifting away
+const CROSSOVER: f32 = 1.5; //default crossover frequency in rad/s
+
+pub struct VerticalEstimator{
+    pub altitude: I16F16, // altitude above the calibration point in m
+    pub velocity: I16F16, // vertical speed in m/s, up is positive
+    pub accel_bias: I16F16, // offset of the vertical acceleration in m/s^2
+    pub crossover: I22F10, // below this frequency in rad/s the barometer is trusted
+    k1: I16F16,
+    k2: I16F16,
+    k3: I16F16,
+}
+
+///Third order complementary filter
+///e = baro - altitude
+///altitude' = velocity + k1*e
+///velocity' = accel - accel_bias + k2*e
+///accel_bias' = -k3*e
+///with k1 = 3w, k2 = 3w^2 and k3 = w^3 all three poles are at the crossover frequency w,
+///so the bias of the accelerometer is learned and does not show up as a drift of the altitude
+impl VerticalEstimator{
+    pub fn new() -> Self {
+        let mut estimator = VerticalEstimator{
+            altitude: I16F16::from_num(0),
+            velocity: I16F16::from_num(0),
+            accel_bias: I16F16::from_num(0),
+            crossover: I22F10::from_num(CROSSOVER),
+            k1: I16F16::from_num(0),
+            k2: I16F16::from_num(0),
+            k3: I16F16::from_num(0),
+        };
+        estimator.calculate_gains();
+        estimator
+    }
+
+    //change the crossover frequency, the state is kept
+    pub fn set_crossover(&mut self, crossover: I22F10) {
+        self.crossover = crossover;
+        self.calculate_gains();
+    }
+
+    fn calculate_gains(&mut self) {
+        let w = I16F16::from_num(self.crossover);
+        self.k1 = 3 * w;
+        self.k2 = 3 * w * w;
+        self.k3 = w * w * w;
+    }
+
+    //start over at rest on the given altitude, done on the ground by the calibration
+    pub fn reset(&mut self, altitude: I22F10) {
+        self.altitude = I16F16::from_num(altitude);
+        self.velocity = I16F16::from_num(0);
+        self.accel_bias = I16F16::from_num(0);
+    }
+
+    //one step with the vertical acceleration in m/s^2 without gravity and the barometer altitude in m
+    pub fn update(&mut self, accel: I22F10, baro: I22F10, dt_us: u32) {
+        let dt = I16F16::from_num(dt_us as f32 / 1_000_000.0);
+        let error = I16F16::from_num(baro) - self.altitude;
+        let accel = I16F16::from_num(accel) - self.accel_bias;
+        self.accel_bias -= self.k3 * error * dt;
+        self.velocity += (accel + self.k2 * error) * dt;
+        self.altitude += (self.velocity + self.k1 * error) * dt;
+    }
+}
+
+impl Default for VerticalEstimator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,93 @@
+//! Everything the control code needs from the board, so `Drone` can run on the
+//! quadrupel firmware as well as on the host.
+#[cfg(feature = "quadrupel")]
+mod quadrupel;
+// the board in memory, for the tests and the host builds
+#[cfg(any(test, not(feature = "quadrupel")))]
+mod mock;
+
+#[cfg(feature = "quadrupel")]
+pub use quadrupel::QuadrupelHal;
+#[cfg(any(test, not(feature = "quadrupel")))]
+pub use mock::MockHal;
+
+// the HAL a Drone uses when none is named
+#[cfg(feature = "quadrupel")]
+pub type DefaultHal = QuadrupelHal;
+#[cfg(not(feature = "quadrupel"))]
+pub type DefaultHal = MockHal;
+
+/// Attitude quaternion from the motion processor
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Quaternion {
+    pub w: f32,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+/// Raw accelerometer reading
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Accel {
+    pub x: i16,
+    pub y: i16,
+    pub z: i16,
+}
+
+/// Raw gyroscope reading
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Gyro {
+    pub x: i16,
+    pub y: i16,
+    pub z: i16,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Led {
+    Red,
+    Green,
+    Blue,
+    Yellow,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FlashError;
+
+pub trait DroneHal {
+    // motors
+    fn set_motors(&mut self, motors: [u16; 4]);
+    fn get_motors(&self) -> [u16; 4];
+    fn set_motor_max(&mut self, max: u16);
+
+    // sensors
+    /// blocks until the motion processor has a new attitude
+    fn read_dmp(&mut self) -> Quaternion;
+    fn read_raw(&mut self) -> (Accel, Gyro);
+    /// pressure in Pa
+    fn read_pressure(&mut self) -> u32;
+    /// battery voltage in centivolts
+    fn read_battery(&mut self) -> u16;
+
+    // uart
+    fn send_bytes(&mut self, bytes: &[u8]) -> bool;
+    /// returns the number of bytes written to `buffer`
+    fn receive_bytes(&mut self, buffer: &mut [u8]) -> usize;
+
+    // flash
+    fn flash_read(&mut self, address: u32, buffer: &mut [u8]) -> Result<(), FlashError>;
+    fn flash_write(&mut self, address: u32, bytes: &[u8]) -> Result<(), FlashError>;
+    fn flash_erase(&mut self) -> Result<(), FlashError>;
+
+    // leds
+    fn led_on(&mut self, led: Led);
+    fn led_off(&mut self, led: Led);
+    fn led_toggle(&mut self, led: Led);
+
+    // time
+    /// microseconds since boot
+    fn now_us(&self) -> u64;
+    fn set_tick_frequency(&mut self, hz: u64);
+    fn wait_for_next_tick(&mut self);
+    /// busy wait for about `cycles` processor cycles
+    fn delay(&mut self, cycles: u32);
+}
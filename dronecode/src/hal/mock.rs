@@ -0,0 +1,153 @@
+use alloc::collections::VecDeque;
+use alloc::vec;
+use alloc::vec::Vec;
+use super::{Accel, DroneHal, FlashError, Gyro, Led, Quaternion};
+
+// size of the flash chip on the drone
+const FLASH_SIZE: usize = 0x20000;
+
+/// A drone without hardware, for running the control code on the host.
+///
+/// Sensor readings are whatever the test puts in the public fields, bytes sent
+/// over the uart are collected in `tx` and `rx` is handed out to `receive_bytes`.
+/// Time only moves on `wait_for_next_tick` and `delay`.
+pub struct MockHal {
+    pub motors: [u16; 4],
+    pub motor_max: u16,
+    pub dmp: Quaternion,
+    pub accel: Accel,
+    pub gyro: Gyro,
+    pub pressure: u32,
+    pub battery: u16,
+    pub tx: Vec<u8>,
+    pub rx: VecDeque<u8>,
+    pub flash: Vec<u8>,
+    pub leds: [bool; 4],
+    pub time_us: u64,
+    pub tick_us: u64,
+}
+
+impl MockHal {
+    pub fn new() -> Self {
+        MockHal {
+            motors: [0; 4],
+            motor_max: u16::MAX,
+            // level and pointing north
+            dmp: Quaternion { w: 1.0, x: 0.0, y: 0.0, z: 0.0 },
+            accel: Accel::default(),
+            gyro: Gyro::default(),
+            pressure: 101325,
+            battery: 1200,
+            tx: Vec::new(),
+            rx: VecDeque::new(),
+            flash: vec![0xFF; FLASH_SIZE],
+            leds: [false; 4],
+            time_us: 0,
+            tick_us: 10_000,
+        }
+    }
+
+    pub fn led(&self, led: Led) -> bool {
+        self.leds[led as usize]
+    }
+}
+
+impl Default for MockHal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DroneHal for MockHal {
+    fn set_motors(&mut self, motors: [u16; 4]) {
+        self.motors = motors.map(|m| m.min(self.motor_max));
+    }
+
+    fn get_motors(&self) -> [u16; 4] {
+        self.motors
+    }
+
+    fn set_motor_max(&mut self, max: u16) {
+        self.motor_max = max;
+    }
+
+    fn read_dmp(&mut self) -> Quaternion {
+        self.dmp
+    }
+
+    fn read_raw(&mut self) -> (Accel, Gyro) {
+        (self.accel, self.gyro)
+    }
+
+    fn read_pressure(&mut self) -> u32 {
+        self.pressure
+    }
+
+    fn read_battery(&mut self) -> u16 {
+        self.battery
+    }
+
+    fn send_bytes(&mut self, bytes: &[u8]) -> bool {
+        self.tx.extend_from_slice(bytes);
+        true
+    }
+
+    fn receive_bytes(&mut self, buffer: &mut [u8]) -> usize {
+        let len = buffer.len().min(self.rx.len());
+        for (slot, byte) in buffer.iter_mut().zip(self.rx.drain(..len)) {
+            *slot = byte;
+        }
+        len
+    }
+
+    fn flash_read(&mut self, address: u32, buffer: &mut [u8]) -> Result<(), FlashError> {
+        let start = address as usize;
+        let data = self.flash.get(start..start + buffer.len()).ok_or(FlashError)?;
+        buffer.copy_from_slice(data);
+        Ok(())
+    }
+
+    fn flash_write(&mut self, address: u32, bytes: &[u8]) -> Result<(), FlashError> {
+        let start = address as usize;
+        let data = self.flash.get_mut(start..start + bytes.len()).ok_or(FlashError)?;
+        // like NOR flash, writing can only clear bits
+        for (cell, byte) in data.iter_mut().zip(bytes) {
+            *cell &= byte;
+        }
+        Ok(())
+    }
+
+    fn flash_erase(&mut self) -> Result<(), FlashError> {
+        self.flash.fill(0xFF);
+        Ok(())
+    }
+
+    fn led_on(&mut self, led: Led) {
+        self.leds[led as usize] = true;
+    }
+
+    fn led_off(&mut self, led: Led) {
+        self.leds[led as usize] = false;
+    }
+
+    fn led_toggle(&mut self, led: Led) {
+        self.leds[led as usize] = !self.leds[led as usize];
+    }
+
+    fn now_us(&self) -> u64 {
+        self.time_us
+    }
+
+    fn set_tick_frequency(&mut self, hz: u64) {
+        self.tick_us = 1_000_000 / hz;
+    }
+
+    fn wait_for_next_tick(&mut self) {
+        self.time_us += self.tick_us;
+    }
+
+    fn delay(&mut self, cycles: u32) {
+        // the nrf51 runs at 16MHz
+        self.time_us += cycles as u64 / 16;
+    }
+}
@@ -0,0 +1,111 @@
+use tudelft_quadrupel::{barometer, battery, block, flash, motor, mpu, time, uart};
+use tudelft_quadrupel::led::Led as BoardLed;
+use tudelft_quadrupel::time::Instant;
+use super::{Accel, DroneHal, FlashError, Gyro, Led, Quaternion};
+
+//the drone itself, every call goes to the tudelft_quadrupel drivers
+pub struct QuadrupelHal {
+    boot: Instant,
+}
+
+impl QuadrupelHal {
+    //the drivers must already be initialized
+    pub fn new() -> Self {
+        QuadrupelHal { boot: Instant::now() }
+    }
+}
+
+impl Default for QuadrupelHal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn board_led(led: Led) -> BoardLed {
+    match led {
+        Led::Red => BoardLed::Red,
+        Led::Green => BoardLed::Green,
+        Led::Blue => BoardLed::Blue,
+        Led::Yellow => BoardLed::Yellow,
+    }
+}
+
+impl DroneHal for QuadrupelHal {
+    fn set_motors(&mut self, motors: [u16; 4]) {
+        motor::set_motors(motors);
+    }
+
+    fn get_motors(&self) -> [u16; 4] {
+        motor::get_motors()
+    }
+
+    fn set_motor_max(&mut self, max: u16) {
+        motor::set_motor_max(max);
+    }
+
+    fn read_dmp(&mut self) -> Quaternion {
+        let q = block!(mpu::read_dmp_bytes()).unwrap();
+        Quaternion { w: q.w.to_num::<f32>(), x: q.x.to_num::<f32>(), y: q.y.to_num::<f32>(), z: q.z.to_num::<f32>() }
+    }
+
+    fn read_raw(&mut self) -> (Accel, Gyro) {
+        let (acc, gyro) = mpu::read_raw().unwrap();
+        (Accel { x: acc.x, y: acc.y, z: acc.z }, Gyro { x: gyro.x, y: gyro.y, z: gyro.z })
+    }
+
+    fn read_pressure(&mut self) -> u32 {
+        barometer::read_pressure()
+    }
+
+    fn read_battery(&mut self) -> u16 {
+        battery::read_battery()
+    }
+
+    fn send_bytes(&mut self, bytes: &[u8]) -> bool {
+        uart::send_bytes(bytes)
+    }
+
+    fn receive_bytes(&mut self, buffer: &mut [u8]) -> usize {
+        uart::receive_bytes(buffer)
+    }
+
+    fn flash_read(&mut self, address: u32, buffer: &mut [u8]) -> Result<(), FlashError> {
+        flash::flash_read_bytes(address, buffer).map_err(|_| FlashError)
+    }
+
+    fn flash_write(&mut self, address: u32, bytes: &[u8]) -> Result<(), FlashError> {
+        flash::flash_write_bytes(address, bytes).map_err(|_| FlashError)
+    }
+
+    fn flash_erase(&mut self) -> Result<(), FlashError> {
+        flash::flash_chip_erase().map_err(|_| FlashError)
+    }
+
+    fn led_on(&mut self, led: Led) {
+        board_led(led).on();
+    }
+
+    fn led_off(&mut self, led: Led) {
+        board_led(led).off();
+    }
+
+    fn led_toggle(&mut self, led: Led) {
+        let _ = board_led(led).toggle();
+    }
+
+    fn now_us(&self) -> u64 {
+        Instant::now().duration_since(self.boot).as_micros() as u64
+    }
+
+    fn set_tick_frequency(&mut self, hz: u64) {
+        time::set_tick_frequency(hz);
+    }
+
+    fn wait_for_next_tick(&mut self) {
+        time::wait_for_next_tick();
+    }
+
+    fn delay(&mut self, cycles: u32) {
+        time::assembly_delay(cycles as _);
+    }
+}
@@ -0,0 +1,23 @@
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+//writes a value of Self field by field through a raw pointer, inside an unsafe block and with a
+//pointer that is valid for writes. Put together as a whole, the value would be built on the stack
+//first and then copied, and the drone does not fit on the stack of the board twice.
+//The pattern never runs, it makes the compiler check that no field is left out
+macro_rules! write_fields {
+    ($ptr:expr, Self { $($field:ident $(: $value:expr)?),* $(,)? }) => {{
+        let ptr: *mut Self = $ptr;
+        let _ = |value: Self| {
+            let Self { $($field: _),* } = value;
+        };
+        $(core::ptr::addr_of_mut!((*ptr).$field).write(write_fields!(@value $field $(: $value)?));)*
+    }};
+    (@value $field:ident) => { $field };
+    (@value $field:ident: $value:expr) => { $value };
+}
+
+pub mod control;
+pub mod filters;
+pub mod hal;
@@ -4,7 +4,8 @@
 
 extern crate alloc;
 
-use crate::control::control_loop;
+use dronecode::control::{control_loop, Controller};
+use dronecode::hal::QuadrupelHal;
 use alloc::format;
 use core::alloc::Layout;
 use core::mem::MaybeUninit;
@@ -16,8 +17,6 @@ use tudelft_quadrupel::time::assembly_delay;
 use tudelft_quadrupel::uart::{receive_bytes, send_bytes};
 use tudelft_quadrupel::{cortex_m, entry, uart};
 use tudelft_quadrupel::motor::set_motors;
-mod control;
-pub mod filters;
 
 
 
@@ -46,8 +45,15 @@ fn main() -> ! {
     }
 
 
+    let controller = {
+        static mut CONTROLLER: MaybeUninit<Controller<QuadrupelHal>> = MaybeUninit::uninit();
 
-    control_loop()
+        // SAFETY: like HEAP_MEMORY, CONTROLLER can only be used through this reference.
+        // It is a static because the controller would take up most of the stack otherwise.
+        Controller::init(unsafe { &mut *addr_of_mut!(CONTROLLER) }, QuadrupelHal::new())
+    };
+
+    control_loop(controller)
 }
 
 #[inline(never)]
@@ -0,0 +1,53 @@
+use alloc::vec::Vec;
+/// Consistent Overhead Byte Stuffing
+///
+/// Rewrites a buffer so it contains no zero bytes, at the cost of one extra byte
+/// per 254 bytes of data. Every zero is replaced by the distance to the next zero
+/// and a code byte is put in front, which leaves 0x00 free to mark the end of a frame.
+pub fn encode(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(data.len() + data.len() / 254 + 1);
+    // position of the code byte of the current block
+    let mut code_idx = 0;
+    let mut code: u8 = 1;
+    output.push(0);
+    for &byte in data {
+        if byte == 0 {
+            output[code_idx] = code;
+            code_idx = output.len();
+            output.push(0);
+            code = 1;
+        } else {
+            output.push(byte);
+            code += 1;
+            if code == 0xFF {
+                // block is full, start a new one
+                output[code_idx] = code;
+                code_idx = output.len();
+                output.push(0);
+                code = 1;
+            }
+        }
+    }
+    output[code_idx] = code;
+    output
+}
+
+/// Undo `encode`, the trailing 0x00 delimiter must already be stripped.
+/// Returns None if the data is not valid COBS.
+pub fn decode(data: &[u8]) -> Option<Vec<u8>> {
+    let mut output = Vec::with_capacity(data.len());
+    let mut idx = 0;
+    while idx < data.len() {
+        let code = data[idx] as usize;
+        if code == 0 || idx + code > data.len() {
+            return None;
+        }
+        output.extend_from_slice(&data[idx + 1..idx + code]);
+        idx += code;
+        // a block shorter than 254 bytes stands for a zero, except at the very end
+        if code < 0xFF && idx < data.len() {
+            output.push(0);
+        }
+    }
+    Some(output)
+}
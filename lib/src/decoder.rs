@@ -0,0 +1,252 @@
+use alloc::vec::Vec;
+use crate::{fec, LinkStats, Message, ProtocolError, Version, MESSAGE_LEN, PROTOCOL_VERSION};
+
+const START_BYTE: u8 = 0xFE;
+const END_BYTE: u8 = 0xFF;
+// start byte, length byte, two checksum bytes and end byte
+const FRAME_OVERHEAD: usize = 5;
+const STUFFED_DELIMITER: u8 = 0x00;
+// no command serializes to more than MESSAGE_LEN bytes
+pub const MAX_FRAME_LEN: usize = MESSAGE_LEN + FRAME_OVERHEAD;
+// version byte, sequence number, payload, two checksum bytes and one COBS code byte
+const MAX_PLAIN_LEN: usize = MESSAGE_LEN + 5;
+// flag byte, the encoded length byte and plain body, and one COBS code byte
+const MAX_FEC_LEN: usize = fec::encoded_len(MAX_PLAIN_LEN) + 2;
+const MAX_STUFFED_LEN: usize = if MAX_FEC_LEN > MAX_PLAIN_LEN { MAX_FEC_LEN } else { MAX_PLAIN_LEN };
+// room for the longest frame of either kind, including a stuffed frame's delimiter
+const BUFFER_LEN: usize = if MAX_FRAME_LEN > MAX_STUFFED_LEN + 1 { MAX_FRAME_LEN } else { MAX_STUFFED_LEN + 1 };
+
+/// Streaming decoder for the serial protocol.
+///
+/// Bytes can be fed in chunks of any size. For V1 frames the frame length is
+/// taken from the length byte, so payload and checksum bytes equal to 0xFE or
+/// 0xFF do not end or restart a frame. When a frame candidate turns out to be
+/// corrupt the decoder drops only its start byte and searches the buffered bytes
+/// again for the next start byte, so a real frame hidden behind the garbage is
+/// not lost. V2 and V3 frames are simply cut at every 0x00.
+pub struct FrameDecoder {
+    buffer: [u8; BUFFER_LEN],
+    len: usize,
+    version: Version,
+    // an overlong stuffed frame is being thrown away up to the next delimiter
+    skipping: bool,
+    stats: LinkStats,
+}
+
+impl Default for FrameDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        Self::with_version(PROTOCOL_VERSION)
+    }
+
+    pub fn with_version(version: Version) -> Self {
+        FrameDecoder {
+            buffer: [0; BUFFER_LEN],
+            len: 0,
+            version,
+            skipping: false,
+            stats: LinkStats::new(),
+        }
+    }
+
+    /// Counters of every frame and error produced so far
+    pub fn stats(&self) -> LinkStats {
+        self.stats
+    }
+
+    /// Feed a chunk of received bytes, returning every frame completed by it in order
+    pub fn feed(&mut self, bytes: &[u8]) -> Vec<Result<Message, ProtocolError>> {
+        let mut result = Vec::new();
+        for &byte in bytes {
+            match self.version {
+                Version::V1 => {
+                    self.buffer[self.len] = byte;
+                    self.len += 1;
+                    self.drain(&mut result);
+                }
+                Version::V2 | Version::V3 => self.push_stuffed(byte, &mut result),
+            }
+        }
+        for frame in result.iter() {
+            self.stats.record(frame);
+        }
+        result
+    }
+
+    /// Forget any partially received frame
+    pub fn reset(&mut self) {
+        self.len = 0;
+        self.skipping = false;
+    }
+
+    fn push_stuffed(&mut self, byte: u8, result: &mut Vec<Result<Message, ProtocolError>>) {
+        if byte == STUFFED_DELIMITER {
+            if !self.skipping && self.len > 0 {
+                self.buffer[self.len] = byte;
+                result.push(Message::decode(&self.buffer[..self.len + 1]));
+            }
+            self.reset();
+        } else if !self.skipping {
+            if self.len == MAX_STUFFED_LEN {
+                result.push(Err(ProtocolError::LengthMismatch));
+                self.len = 0;
+                self.skipping = true;
+            } else {
+                self.buffer[self.len] = byte;
+                self.len += 1;
+            }
+        }
+    }
+
+    // parse as many V1 frames as possible out of the buffered bytes
+    fn drain(&mut self, result: &mut Vec<Result<Message, ProtocolError>>) {
+        loop {
+            // skip everything in front of the next start byte
+            match self.buffer[..self.len].iter().position(|&b| b == START_BYTE) {
+                Some(idx) => self.consume(idx),
+                None => {
+                    self.len = 0;
+                    return;
+                }
+            }
+            if self.len < 2 {
+                return;
+            }
+            let frame_len = self.buffer[1] as usize + FRAME_OVERHEAD;
+            if frame_len > MAX_FRAME_LEN {
+                // a false start byte would otherwise hold back real frames
+                result.push(Err(ProtocolError::LengthMismatch));
+                self.consume(1);
+                continue;
+            }
+            if self.len < frame_len {
+                return;
+            }
+            if self.buffer[frame_len - 1] != END_BYTE {
+                // not a real frame, look for a start byte inside it
+                result.push(Err(ProtocolError::BadDelimiter));
+                self.consume(1);
+                continue;
+            }
+            let frame = Message::decode(&self.buffer[..frame_len]);
+            match frame {
+                // a deserialize error still passed the checksum, so it was a real frame
+                Ok(_) | Err(ProtocolError::Deserialize) => self.consume(frame_len),
+                Err(_) => self.consume(1),
+            }
+            result.push(frame);
+        }
+    }
+
+    // drop the first n buffered bytes
+    fn consume(&mut self, n: usize) {
+        self.buffer.copy_within(n..self.len, 0);
+        self.len -= n;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+    use super::*;
+    use crate::{cobs, Command, Mode};
+
+    fn frame(version: Version, command: Command) -> Vec<u8> {
+        Message::with_version(command, version).build_message()
+    }
+
+    fn commands(results: Vec<Result<Message, ProtocolError>>) -> Vec<Result<Command, ProtocolError>> {
+        results.into_iter().map(|r| r.map(Message::into_command)).collect()
+    }
+
+    #[test]
+    fn split_frames() {
+        for version in [Version::V1, Version::V3] {
+            let bytes = frame(version, Command::ThrottleSet { num: -300 });
+            // every split point, and one byte at a time
+            for split in 0..=bytes.len() {
+                let mut decoder = FrameDecoder::with_version(version);
+                let mut results = decoder.feed(&bytes[..split]);
+                results.extend(decoder.feed(&bytes[split..]));
+                assert_eq!(commands(results), vec![Ok(Command::ThrottleSet { num: -300 })]);
+            }
+            let mut decoder = FrameDecoder::with_version(version);
+            let results: Vec<_> = bytes.iter().flat_map(|b| decoder.feed(&[*b])).collect();
+            assert_eq!(commands(results), vec![Ok(Command::ThrottleSet { num: -300 })]);
+        }
+    }
+
+    #[test]
+    fn garbage_between_frames() {
+        for version in [Version::V1, Version::V3] {
+            // stuffed frames are cut at every delimiter, so there the noise ends in one
+            let noise = |bytes: &[u8]| {
+                let mut noise = bytes.to_vec();
+                if version != Version::V1 {
+                    noise.push(STUFFED_DELIMITER);
+                }
+                noise
+            };
+            let mut bytes = noise(&[0x13, 0x37]);
+            bytes.extend(frame(version, Command::ModeChange { mode: Mode::Manual }));
+            // a false start byte with a length that is too long, and one whose frame runs into the next real frame
+            bytes.extend(noise(&[0xFE, 0xF0, 0x99, 0xFE, 0x01]));
+            bytes.extend(frame(version, Command::KeepAlive));
+            let mut decoder = FrameDecoder::with_version(version);
+            let decoded: Vec<_> = commands(decoder.feed(&bytes)).into_iter().filter_map(Result::ok).collect();
+            assert_eq!(decoded, vec![Command::ModeChange { mode: Mode::Manual }, Command::KeepAlive]);
+            assert_eq!(decoder.stats().frames, 2);
+            assert!(decoder.stats().errors() > 0);
+        }
+    }
+
+    #[test]
+    fn truncated_frame() {
+        // a V1 frame cut short waits for more bytes and does not hide the next frame
+        let first = frame(Version::V1, Command::ThrottleSet { num: 12 });
+        let mut decoder = FrameDecoder::with_version(Version::V1);
+        assert!(decoder.feed(&first[..first.len() - 2]).is_empty());
+        let results = commands(decoder.feed(&frame(Version::V1, Command::KeepAlive)));
+        assert_eq!(results.last(), Some(&Ok(Command::KeepAlive)));
+        assert!(results[..results.len() - 1].iter().all(Result::is_err));
+
+        // a stuffed frame cut short ends at the next delimiter and is rejected
+        let first = frame(Version::V3, Command::ThrottleSet { num: 12 });
+        let mut bytes = first[..first.len() / 2].to_vec();
+        bytes.push(0x00);
+        bytes.extend(frame(Version::V3, Command::KeepAlive));
+        let mut decoder = FrameDecoder::with_version(Version::V3);
+        let results = commands(decoder.feed(&bytes));
+        assert_eq!(results.len(), 2);
+        assert!(results[0].is_err());
+        assert_eq!(results[1], Ok(Command::KeepAlive));
+        assert_eq!(decoder.stats().errors(), 1);
+    }
+
+    #[test]
+    fn bad_crc() {
+        // the last payload byte changed to another value that keeps the framing intact
+        let mut bytes = frame(Version::V1, Command::ThrottleSet { num: 5 });
+        let payload = bytes.len() - 4;
+        bytes[payload] ^= 0x01;
+        let mut decoder = FrameDecoder::with_version(Version::V1);
+        assert_eq!(commands(decoder.feed(&bytes)), vec![Err(ProtocolError::ChecksumMismatch)]);
+        assert_eq!(decoder.stats().checksum_mismatch, 1);
+
+        let stuffed = frame(Version::V3, Command::ThrottleSet { num: 5 });
+        let mut body = cobs::decode(&stuffed[..stuffed.len() - 1]).unwrap();
+        let last = body.len() - 1;
+        body[last] ^= 0x01;
+        let mut bytes = cobs::encode(&body);
+        bytes.push(0x00);
+        let mut decoder = FrameDecoder::with_version(Version::V3);
+        assert_eq!(commands(decoder.feed(&bytes)), vec![Err(ProtocolError::ChecksumMismatch)]);
+        assert_eq!(decoder.stats().checksum_mismatch, 1);
+        assert_eq!(decoder.stats().frames, 0);
+    }
+}
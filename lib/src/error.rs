@@ -0,0 +1,55 @@
+use serde::{Serialize, Deserialize};
+
+/// Reasons a received frame or log record is rejected
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ProtocolError {
+    /// the frame does not start or end with the expected delimiter, or its stuffing is invalid
+    BadDelimiter,
+    /// the frame is shorter or longer than its header or the protocol allows
+    LengthMismatch,
+    /// the checksum does not match the received bytes
+    ChecksumMismatch,
+    /// the checksum is fine but the payload is not a valid command
+    Deserialize,
+    /// the version byte of a stuffed frame is not known to this build
+    UnknownVersion,
+    /// an LDPC encoded block has more bit errors than can be corrected
+    Uncorrectable,
+}
+
+/// Number of decoded frames and of each class of protocol error
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy, Default)]
+pub struct LinkStats {
+    pub frames: u32,
+    pub bad_delimiter: u32,
+    pub length_mismatch: u32,
+    pub checksum_mismatch: u32,
+    pub deserialize: u32,
+    pub unknown_version: u32,
+    pub uncorrectable: u32,
+}
+
+impl LinkStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record<T>(&mut self, result: &Result<T, ProtocolError>) {
+        let counter = match result {
+            Ok(_) => &mut self.frames,
+            Err(ProtocolError::BadDelimiter) => &mut self.bad_delimiter,
+            Err(ProtocolError::LengthMismatch) => &mut self.length_mismatch,
+            Err(ProtocolError::ChecksumMismatch) => &mut self.checksum_mismatch,
+            Err(ProtocolError::Deserialize) => &mut self.deserialize,
+            Err(ProtocolError::UnknownVersion) => &mut self.unknown_version,
+            Err(ProtocolError::Uncorrectable) => &mut self.uncorrectable,
+        };
+        *counter = counter.wrapping_add(1);
+    }
+
+    /// Total number of rejected frames
+    pub fn errors(&self) -> u32 {
+        self.bad_delimiter + self.length_mismatch + self.checksum_mismatch
+            + self.deserialize + self.unknown_version + self.uncorrectable
+    }
+}
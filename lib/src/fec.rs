@@ -0,0 +1,110 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use labrador_ldpc::LDPCCode;
+
+/// Forward error correction with the (256, 128) LDPC code from labrador-ldpc.
+///
+/// Data is cut in blocks of 16 bytes, the last one padded with zeros, and every
+/// block becomes a 32 byte codeword. The bit-flipping decoder corrects any one or
+/// two flipped bits per codeword, and most patterns of a few more, which is enough
+/// for the single-bit errors we see on the serial link and in flash.
+const CODE: LDPCCode = LDPCCode::TC256;
+// data bytes per codeword, k / 8
+const DATA_LEN: usize = 16;
+// bytes per codeword, n / 8
+const BLOCK_LEN: usize = 32;
+const MAX_ITERS: usize = 20;
+
+/// Number of bytes `encode` turns `len` bytes of data into
+pub const fn encoded_len(len: usize) -> usize {
+    len.div_ceil(DATA_LEN) * BLOCK_LEN
+}
+
+pub fn encode(data: &[u8]) -> Vec<u8> {
+    let mut output = vec![0; encoded_len(data.len())];
+    for (block, codeword) in data.chunks(DATA_LEN).zip(output.chunks_mut(BLOCK_LEN)) {
+        let mut padded = [0; DATA_LEN];
+        padded[..block.len()].copy_from_slice(block);
+        CODE.copy_encode(&padded, codeword);
+    }
+    output
+}
+
+/// Undo `encode`, correcting bit errors on the way. The padding of the last
+/// block is returned as well. Returns None if the data is not a whole number of
+/// codewords or a codeword has more errors than the decoder can correct.
+pub fn decode(data: &[u8]) -> Option<Vec<u8>> {
+    if data.is_empty() || !data.len().is_multiple_of(BLOCK_LEN) {
+        return None;
+    }
+    let mut output = Vec::with_capacity(data.len() / BLOCK_LEN * DATA_LEN);
+    let mut decoded = vec![0; CODE.output_len()];
+    let mut working = vec![0; CODE.decode_bf_working_len()];
+    for codeword in data.chunks(BLOCK_LEN) {
+        let (ok, _) = CODE.decode_bf(codeword, &mut decoded, &mut working, MAX_ITERS);
+        if !ok {
+            return None;
+        }
+        output.extend_from_slice(&decoded[..DATA_LEN]);
+    }
+    Some(output)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{serialize_message_log, Command, Message, Mode, LOG_FEC_RECORD_LEN};
+
+    fn flip(data: &mut [u8], bit: usize) {
+        data[bit / 8] ^= 1 << (bit % 8);
+    }
+
+    #[test]
+    fn corrects_up_to_two_bits_per_codeword() {
+        let data: Vec<u8> = (0..DATA_LEN as u8).map(|b| b.wrapping_mul(37) ^ 0x5a).collect();
+        let encoded = encode(&data);
+        for first in 0..BLOCK_LEN * 8 {
+            let mut one = encoded.clone();
+            flip(&mut one, first);
+            assert_eq!(decode(&one).as_deref(), Some(&data[..]), "bit {first}");
+            for second in first + 1..BLOCK_LEN * 8 {
+                let mut two = one.clone();
+                flip(&mut two, second);
+                assert_eq!(decode(&two).as_deref(), Some(&data[..]), "bits {first} and {second}");
+            }
+        }
+    }
+
+    #[test]
+    fn corrects_every_codeword_of_a_log_record() {
+        let cmd = Command::Datalog { mode: Mode::Raw, ypr: [0.1, -0.2, 0.3], raw_ypr: [1.0, 2.0, 3.0],
+            motor: [200, 300, 400, 500], time: 123456, raw_speed: 0.5, tick: 42 };
+        let mut message = Message::with_fec(cmd.clone());
+        let record = message.build_message_log();
+        assert_eq!(record.len(), LOG_FEC_RECORD_LEN);
+        // two errors in each codeword, at places that move from one codeword to the next
+        let mut corrupted = record.clone();
+        for (block, start) in (0..record.len()).step_by(BLOCK_LEN).enumerate() {
+            flip(&mut corrupted, start * 8 + block * 37 % 256);
+            flip(&mut corrupted, start * 8 + (block * 91 + 200) % 256);
+        }
+        assert_eq!(Message::get_message_log(&corrupted), Ok(cmd));
+        // a plain record has no such protection
+        let mut plain = serialize_message_log(Command::KeepAlive);
+        flip(&mut plain, 20);
+        assert!(Message::get_message_log(&plain).is_err());
+    }
+
+    #[test]
+    fn rejects_what_it_cannot_correct() {
+        let encoded = encode(&[0xA5; DATA_LEN]);
+        let mut corrupted = encoded.clone();
+        for bit in (0..BLOCK_LEN * 8).step_by(5) {
+            flip(&mut corrupted, bit);
+        }
+        assert_eq!(decode(&corrupted), None);
+        assert_eq!(decode(&encoded[..BLOCK_LEN - 1]), None);
+        assert_eq!(decode(&[]), None);
+    }
+}
@@ -7,6 +7,18 @@ use alloc::vec::Vec;
 use postcard::{to_allocvec, from_bytes};
 use fixed::types::I22F10;
 use crc_any::CRCu16;
+
+pub mod cobs;
+pub mod decoder;
+pub mod error;
+pub mod fec;
+pub mod params;
+pub mod telemetry;
+pub use decoder::FrameDecoder;
+pub use error::{LinkStats, ProtocolError};
+pub use params::{ParamInfo, PARAM_NAME_LEN};
+pub use telemetry::{Telemetry, DEFAULT_TELEMETRY_PERIOD};
+
 // Keepalive timers
 pub const KEEPALIVE_T_MS: u128 = 100; // Keepalive timer in ms
 pub const KEEPALIVE_TX_MS: u128 = KEEPALIVE_T_MS-50; // Keepalive timer for sending in ns
@@ -14,12 +26,47 @@ pub const YAW_SCALE: f32 = 200.0; // Scale the val form joystick to motor cal
 pub const PITCH_SCALE: f32 = 800.0; // Scale the val form joystick to motor cal
 pub const ROLL_SCALE: f32 = 800.0; // Scale the val form joystick to motor cal
 pub const THROTTLE_SCALE: f32 = 1000.0; // Scale the val form joystick to motor cal
-pub const MESSAGE_LEN: usize = 64;
+pub const MESSAGE_LEN: usize = 72; // a full telemetry frame takes 68
+// Framing used by serialize_message and the frame decoders on both sides
+pub const PROTOCOL_VERSION: Version = Version::V3;
+// Flash log records are written in fixed size slots
+pub const LOG_RECORD_LEN: usize = 64;
+const LOG_PAYLOAD_LEN: usize = LOG_RECORD_LEN - 5;
+// Size of a log record after LDPC encoding
+pub const LOG_FEC_RECORD_LEN: usize = fec::encoded_len(LOG_RECORD_LEN);
+// Start byte of a log record protected by the full CRC instead of the byte sum
+const LOG_START_CRC: u8 = 0xFD;
+// Set in the version byte of a stuffed frame when a sequence number follows it
+const SEQ_FLAG: u8 = 0x80;
+// Set in the first byte of a stuffed frame whose body is LDPC encoded
+const FEC_FLAG: u8 = 0x40;
+// Layout revision of Command and Mode, checked in the Hello handshake.
+// Postcard encodes enum variants by index, so bump this whenever a variant or field changes.
+pub const PROTOCOL_REVISION: u16 = 9;
+
+/// Frame layout on the serial link
+///
+/// V1: 0xFE, len, payload, checksum(2), 0xFF. Payload bytes are not escaped, so
+/// the receiver can only rely on the length byte to find the end of a frame.
+/// V2: COBS(version, payload, checksum(2)), 0x00. The stuffing guarantees that
+/// 0x00 only ever appears as the frame delimiter.
+/// V3: same layout as V2, but the checksum is the full CRC-16/CCITT-FALSE over
+/// the version byte and payload, and log records carry a CRC instead of a byte sum.
+///
+/// A stuffed frame may carry a sequence number right after the version byte; the
+/// receiver answers such a frame with Ack or Nack. SEQ_FLAG marks its presence.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+pub enum Version {
+    V1 = 1,
+    V2 = 2,
+    V3 = 3,
+}
 
 
 // drone mode
-#[derive(Serialize, Deserialize, PartialEq,Clone,Copy)]
+#[derive(Serialize, Deserialize, PartialEq,Clone,Copy,Debug,Default)]
 pub enum Mode {
+    #[default]
     Safe,
     Panic,
     Manual,
@@ -31,9 +78,23 @@ pub enum Mode {
     LogOut
 }
 
+impl Mode {
+    pub const ALL: [Mode; 9] = [Mode::Safe, Mode::Panic, Mode::Manual, Mode::Calibration,
+        Mode::YawControlled, Mode::FullControl, Mode::Raw, Mode::Height, Mode::LogOut];
+
+    //bit of this mode in the supported modes mask of the handshake
+    pub fn bit(self) -> u16 {
+        1 << self as u16
+    }
+}
+
 //communication and log  command
-#[derive(Serialize, Deserialize, PartialEq,Clone)]
+#[derive(Serialize, Deserialize, PartialEq,Clone,Debug)]
 pub enum Command {
+    //the handshake comes first and never moves or changes, so a peer of any other revision
+    //can still decode it and find out that it does not understand the rest
+    Hello{revision: u16},
+    HelloAck{revision: u16, build_id: u32, modes: u16},
     EXIT,
     KeepAlive,
     ModeChange{mode:Mode},
@@ -43,7 +104,6 @@ pub enum Command {
     PitchDSet{num: i16},
     RollPSet{num: i16},
     RollDSet{num: i16},
-    Height{num:i32},
     Time{num:u128},
     ThrottleSet{num: i16},
     YawBack{num: i32},
@@ -53,9 +113,6 @@ pub enum Command {
     YawSet{num: i32},
     PitchSet{num:i32},
     RollSet{num:i32},
-    Trueyaw{num : i32},
-    Truepitch{num : i32},
-    Trueroll{num:i32},
     Speed{num:i32},
     Datalog{
       mode: Mode,
@@ -63,7 +120,8 @@ pub enum Command {
       raw_ypr:[f32;3],
       motor:[u16;4],
       time: u128,
-      raw_speed:f32
+      raw_speed:f32,
+      tick: u32,
     },
     Motor{
         motor1: u16,
@@ -71,19 +129,27 @@ pub enum Command {
         motor3: u16,
         motor4: u16,
     },
-    Motor1{
-        num: u16,
-    },
-    Motor2{
-        num: u16,
-    },
-    Motor3{
-        num: u16,
-    },
-    Motor4{
-        num: u16,
-    },
-    BatteryCheck{num: u16},
+    LinkHealth{stats: LinkStats},
+    Ack{seq: u8},
+    Nack{seq: u8},
+    Telemetry{data: Telemetry},
+    //ticks between two telemetry frames, 0 turns telemetry off
+    TelemetryPeriod{ticks: u16},
+    //generic access to the tunables of the drone, the drone answers Set and Get with ParamValue
+    ParamSet{id: u8, value: I22F10},
+    ParamGet{id: u8},
+    ParamList,
+    ParamValue{id: u8, value: I22F10},
+    ParamInfo{info: ParamInfo},
+    //send telemetry and log dumps LDPC encoded
+    LinkFec{enabled: bool},
+    //clock synchronisation, the drone answers with its own time as soon as the request arrives
+    TimeSyncRequest{pc_time_us: u64},
+    TimeSyncReply{pc_time_us: u64, drone_time_us: u64, tick: u32},
+    //the parameters and the calibration kept in flash, only carried out in safe mode
+    ConfigSave,
+    ConfigLoad,
+    ConfigReset,
 }
 
 impl Command {
@@ -91,6 +157,18 @@ impl Command {
     pub fn serialize(&self) -> Vec<u8> {
         to_allocvec(self).unwrap()
     }
+
+    //commands that have to be acknowledged by the drone and are retransmitted until they are
+    pub fn needs_ack(&self) -> bool {
+        matches!(self,
+            Command::EXIT | Command::ModeChange {..} |
+            Command::YawPSet {..} | Command::YawDSet {..} |
+            Command::PitchPSet {..} | Command::PitchDSet {..} |
+            Command::RollPSet {..} | Command::RollDSet {..} |
+            Command::TelemetryPeriod {..} | Command::ParamSet {..} |
+            Command::LinkFec {..} |
+            Command::ConfigSave | Command::ConfigLoad | Command::ConfigReset)
+    }
 }
 
 #[derive(Serialize, Deserialize, PartialEq)]
@@ -98,33 +176,69 @@ pub struct Message {
     start_byte: u8,
     command: Command,
     end_byte: u8,
+    version: Version,
+    seq: Option<u8>,
+    fec: bool,
 }
 
 impl Message {
     pub fn new(command: Command) -> Self{
+        Self::with_version(command, PROTOCOL_VERSION)
+    }
+
+    pub fn with_version(command: Command, version: Version) -> Self{
         Self {
             start_byte: 0xFE,
             command,
             end_byte: 0xFF,
+            version,
+            seq: None,
+            fec: false,
         }
     }
+
+    //a message the receiver has to answer with Ack or Nack, only stuffed frames can carry it
+    pub fn with_seq(command: Command, seq: u8) -> Self{
+        let mut message = Self::new(command);
+        message.seq = Some(seq);
+        message
+    }
+
+    //a message whose frame or log record is LDPC encoded, see the fec module
+    pub fn with_fec(command: Command) -> Self{
+        let mut message = Self::new(command);
+        message.fec = true;
+        message
+    }
+
+    pub fn command(&self) -> &Command {
+        &self.command
+    }
+
+    pub fn into_command(self) -> Command {
+        self.command
+    }
+
+    pub fn seq(&self) -> Option<u8> {
+        self.seq
+    }
+
     //serialize the command as a message according to our protocol
     pub fn build_message(&mut self) -> Vec<u8> {
+        match self.version {
+            Version::V1 => self.build_message_v1(),
+            Version::V2 | Version::V3 => self.build_message_stuffed(),
+        }
+    }
+
+    fn build_message_v1(&mut self) -> Vec<u8> {
         //get serialized command
         let serialized_command = self.command.serialize();
 
         //get command length
         let len = serialized_command.len() as u8;
 
-        // Create CRC instance
-        let mut crc = CRCu16::crc16ccitt_false();
-
-        // Update CRC with data
-        crc.digest(&serialized_command);
-
-        // Get CRC value
-        let temp = crc.get_crc();
-        let check_sum:[u8;2] = (temp%511).to_be_bytes();
+        let check_sum = check_sum(&serialized_command);
 
         //package all info to protocol format
         let mut buffer = Vec::new();
@@ -132,37 +246,117 @@ impl Message {
         buffer.push(len);
         buffer.extend_from_slice(serialized_command.as_slice());
         // buffer.push(0x20);   // test for unmatched check_sum
-        let t1 = check_sum[0]%253;
-        let t2 = check_sum[1]%253;
-        buffer.push(t1);
-        buffer.push(t2);
+        buffer.push(check_sum[0]);
+        buffer.push(check_sum[1]);
         buffer.push(self.end_byte);
         buffer
     }
 
-    pub fn get_message(received_message: &[u8]) -> Option<Command>{
+    fn build_message_stuffed(&mut self) -> Vec<u8> {
+        let serialized_command = self.command.serialize();
+
+        // version, sequence number, payload and checksum are stuffed together
+        let mut body = Vec::with_capacity(serialized_command.len() + 4);
+        match self.seq {
+            Some(seq) => {
+                body.push(self.version as u8 | SEQ_FLAG);
+                body.push(seq);
+            }
+            None => body.push(self.version as u8),
+        }
+        body.extend_from_slice(serialized_command.as_slice());
+        let check_sum = match self.version {
+            Version::V3 => crc16(&body),
+            _ => check_sum(&serialized_command),
+        };
+        body.extend_from_slice(&check_sum);
+        if self.fec {
+            // the length byte tells the receiver where the padding of the last block starts
+            let mut inner = Vec::with_capacity(body.len() + 1);
+            inner.push(body.len() as u8);
+            inner.extend_from_slice(&body);
+            body = Vec::with_capacity(fec::encoded_len(inner.len()) + 1);
+            body.push(self.version as u8 | FEC_FLAG);
+            body.extend_from_slice(&fec::encode(&inner));
+        }
+
+        let mut buffer = cobs::encode(&body);
+        buffer.push(0x00);
+        buffer
+    }
+
+    pub fn get_message(received_message: &[u8]) -> Result<Command, ProtocolError>{
+        Self::decode(received_message).map(Message::into_command)
+    }
+
+    //a frame ending in 0x00 can only be a stuffed frame, V1 frames end in 0xFF
+    pub fn decode(received_message: &[u8]) -> Result<Message, ProtocolError>{
+        if received_message.ends_with(&[0x00]) {
+            Self::decode_stuffed(received_message)
+        } else {
+            Self::decode_v1(received_message)
+        }
+    }
+
+    fn decode_v1(received_message: &[u8]) -> Result<Message, ProtocolError>{
         let length = received_message.len();
-        if received_message.starts_with(&[0xFE]) && received_message.ends_with(&[0xFF]) {
-            let expected_len = received_message[1] as usize ;
-            let expected_check_sum = received_message[length - 3] ;
-            let expected_check_sum1 = received_message[length - 2] ;
-            let received_command = &received_message[2..length - 3];
-
-            // Create CRC instance
-            let mut crc = CRCu16::crc16ccitt_false();
-
-            // Update CRC with data
-            crc.digest(&received_command);
-
-            // Get CRC value
-            let calculate_check_sum = crc.get_crc();
-            let verify_sum:[u8;2] = (calculate_check_sum%511).to_be_bytes();
-            if (expected_len == received_command.len())&&(expected_check_sum == verify_sum[0]%253) && (expected_check_sum1 == verify_sum[1]%253) {
-                let command: Command = from_bytes(received_command).unwrap();
-                return Some(command)
+        if !received_message.starts_with(&[0xFE]) || !received_message.ends_with(&[0xFF]) {
+            return Err(ProtocolError::BadDelimiter);
+        }
+        // start byte, length byte, checksum and end byte
+        if length < 5 {
+            return Err(ProtocolError::LengthMismatch);
+        }
+        let expected_len = received_message[1] as usize ;
+        let expected_check_sum = &received_message[length - 3..length - 1];
+        let received_command = &received_message[2..length - 3];
+        if expected_len != received_command.len() {
+            return Err(ProtocolError::LengthMismatch);
+        }
+        if check_sum(received_command) != expected_check_sum {
+            return Err(ProtocolError::ChecksumMismatch);
+        }
+        let command = from_bytes(received_command).map_err(|_| ProtocolError::Deserialize)?;
+        Ok(Self::with_version(command, Version::V1))
+    }
+
+    fn decode_stuffed(received_message: &[u8]) -> Result<Message, ProtocolError>{
+        let mut body = cobs::decode(&received_message[..received_message.len() - 1])
+            .ok_or(ProtocolError::BadDelimiter)?;
+        let fec = body.first().is_some_and(|&b| b & FEC_FLAG != 0);
+        if fec {
+            let inner = fec::decode(&body[1..]).ok_or(ProtocolError::Uncorrectable)?;
+            let len = inner[0] as usize;
+            if len + 1 > inner.len() {
+                return Err(ProtocolError::LengthMismatch);
             }
+            body = inner[1..len + 1].to_vec();
+        }
+        // version byte, optional sequence number, at least one payload byte and two checksum bytes
+        let header_len = if body.first().is_some_and(|&b| b & SEQ_FLAG != 0) { 2 } else { 1 };
+        if body.len() < header_len + 3 {
+            return Err(ProtocolError::LengthMismatch);
+        }
+        let (checked, expected_check_sum) = body.split_at(body.len() - 2);
+        let received_command = &checked[header_len..];
+        let version = body[0] & !SEQ_FLAG;
+        let (version, verify_sum) = if version == Version::V3 as u8 {
+            (Version::V3, crc16(checked))
+        } else if version == Version::V2 as u8 {
+            (Version::V2, check_sum(received_command))
+        } else {
+            return Err(ProtocolError::UnknownVersion);
+        };
+        if verify_sum != expected_check_sum {
+            return Err(ProtocolError::ChecksumMismatch);
+        }
+        let command = from_bytes(received_command).map_err(|_| ProtocolError::Deserialize)?;
+        let mut message = Self::with_version(command, version);
+        if header_len == 2 {
+            message.seq = Some(body[1]);
         }
-        None
+        message.fec = fec;
+        Ok(message)
     }
 
     pub fn build_message_log(&mut self) -> Vec<u8> {
@@ -171,47 +365,95 @@ impl Message {
         // Pad serialized command to fixed length
         let padded_serialized_command =  {
             let mut padded = serialized_command.to_vec();
-            padded.resize(59, 0); // Pad with zeros
+            padded.resize(LOG_PAYLOAD_LEN, 0); // Pad with zeros
             padded
         };
 
-        let temp: u16 = padded_serialized_command.iter().map(|&b| b as u16).sum();
-        let check_sum: [u8; 2] = (temp%256).to_be_bytes();
         let mut buffer = Vec::new();
         buffer.push(self.start_byte);
         buffer.push(mes_len);
         buffer.extend_from_slice(&padded_serialized_command);
-        let t1 = check_sum[0];
-        let t2 = check_sum[1];
-        buffer.push(t1);
-        buffer.push(t2);
+        let check_sum = match self.version {
+            Version::V3 => {
+                // length byte and padded payload are covered
+                buffer[0] = LOG_START_CRC;
+                crc16(&buffer[1..])
+            }
+            _ => {
+                let temp: u16 = padded_serialized_command.iter().map(|&b| b as u16).sum();
+                (temp%256).to_be_bytes()
+            }
+        };
+        buffer.push(check_sum[0]);
+        buffer.push(check_sum[1]);
         buffer.push(self.end_byte);
+        if self.fec {
+            return fec::encode(&buffer);
+        }
         buffer
     }
 
-    pub fn get_message_log(received_message: &[u8]) -> Option<Command>{
+    //LOG_FEC_RECORD_LEN bytes are an LDPC encoded record,
+    //otherwise the start byte tells whether the record is protected by the byte sum or the CRC
+    pub fn get_message_log(received_message: &[u8]) -> Result<Command, ProtocolError>{
         let length = received_message.len();
-        if received_message.starts_with(&[0xFE]) && received_message.ends_with(&[0xFF]) {
-            let expected_length = received_message[1];
-            let expected_check_sum = received_message[length - 3];
-            let expected_check_sum1 = received_message[length - 2];
-            let received_command = &received_message[2..(2+expected_length) as usize];
-            let calculate_check_sum: u16 = received_command.iter().map(|&b| b as u16).sum();
-            let verify_sum:[u8;2] = (calculate_check_sum%256).to_be_bytes();
-            if (expected_check_sum == verify_sum[0]) && (expected_check_sum1 == verify_sum[1]) {
-                let command: Command = from_bytes(received_command).unwrap();
-                return Some(command)
+        if length == LOG_FEC_RECORD_LEN {
+            let record = fec::decode(received_message).ok_or(ProtocolError::Uncorrectable)?;
+            return Self::get_message_log(&record);
+        }
+        if !received_message.ends_with(&[0xFF]) {
+            return Err(ProtocolError::BadDelimiter);
+        }
+        if length != LOG_RECORD_LEN {
+            return Err(ProtocolError::LengthMismatch);
+        }
+        let expected_length = received_message[1] as usize;
+        if expected_length > LOG_PAYLOAD_LEN {
+            return Err(ProtocolError::LengthMismatch);
+        }
+        let expected_check_sum = &received_message[length - 3..length - 1];
+        let received_command = &received_message[2..2 + expected_length];
+        let verify_sum = match received_message[0] {
+            LOG_START_CRC => crc16(&received_message[1..length - 3]),
+            0xFE => {
+                let calculate_check_sum: u16 = received_command.iter().map(|&b| b as u16).sum();
+                (calculate_check_sum%256).to_be_bytes()
             }
+            _ => return Err(ProtocolError::BadDelimiter),
+        };
+        if verify_sum != expected_check_sum {
+            return Err(ProtocolError::ChecksumMismatch);
         }
-        None
+        from_bytes(received_command).map_err(|_| ProtocolError::Deserialize)
     }
 
 }
 
+// CRC-16/CCITT-FALSE
+pub fn crc16(data: &[u8]) -> [u8; 2] {
+    let mut crc = CRCu16::crc16ccitt_false();
+    crc.digest(data);
+    crc.get_crc().to_be_bytes()
+}
+
+// CRC-16/CCITT-FALSE, folded so neither byte can be a V1 delimiter
+fn check_sum(data: &[u8]) -> [u8; 2] {
+    let check_sum: [u8; 2] = (u16::from_be_bytes(crc16(data)) % 511).to_be_bytes();
+    [check_sum[0] % 253, check_sum[1] % 253]
+}
+
 pub fn serialize_message(command: Command) -> Vec<u8>{
     let mut mes = Message::new(command);
     mes.build_message()
 }
+pub fn serialize_message_fec(command: Command) -> Vec<u8>{
+    let mut mes = Message::with_fec(command);
+    mes.build_message()
+}
+pub fn serialize_message_seq(command: Command, seq: u8) -> Vec<u8>{
+    let mut mes = Message::with_seq(command, seq);
+    mes.build_message()
+}
 pub fn serialize_message_log(command: Command) -> Vec<u8>{
     let mut mes = Message::new(command);
     mes.build_message_log()
@@ -262,3 +504,132 @@ pub struct Datalog {
     pub yaw_speed_error: I22F10,
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the wire index of every variant. The match has no wildcard, so a new variant does not
+    // compile until it is given its index here, and an index that moves fails the test
+    fn wire_index(command: &Command) -> u8 {
+        match command {
+            Command::Hello { .. } => 0,
+            Command::HelloAck { .. } => 1,
+            Command::EXIT => 2,
+            Command::KeepAlive => 3,
+            Command::ModeChange { .. } => 4,
+            Command::YawPSet { .. } => 5,
+            Command::YawDSet { .. } => 6,
+            Command::PitchPSet { .. } => 7,
+            Command::PitchDSet { .. } => 8,
+            Command::RollPSet { .. } => 9,
+            Command::RollDSet { .. } => 10,
+            Command::Time { .. } => 11,
+            Command::ThrottleSet { .. } => 12,
+            Command::YawBack { .. } => 13,
+            Command::PitchBack { .. } => 14,
+            Command::RollBack { .. } => 15,
+            Command::ThrottleBack { .. } => 16,
+            Command::YawSet { .. } => 17,
+            Command::PitchSet { .. } => 18,
+            Command::RollSet { .. } => 19,
+            Command::Speed { .. } => 20,
+            Command::Datalog { .. } => 21,
+            Command::Motor { .. } => 22,
+            Command::LinkHealth { .. } => 23,
+            Command::Ack { .. } => 24,
+            Command::Nack { .. } => 25,
+            Command::Telemetry { .. } => 26,
+            Command::TelemetryPeriod { .. } => 27,
+            Command::ParamSet { .. } => 28,
+            Command::ParamGet { .. } => 29,
+            Command::ParamList => 30,
+            Command::ParamValue { .. } => 31,
+            Command::ParamInfo { .. } => 32,
+            Command::LinkFec { .. } => 33,
+            Command::TimeSyncRequest { .. } => 34,
+            Command::TimeSyncReply { .. } => 35,
+            Command::ConfigSave => 36,
+            Command::ConfigLoad => 37,
+            Command::ConfigReset => 38,
+        }
+    }
+
+    #[test]
+    fn variant_indices_are_pinned() {
+        let value = I22F10::from_num(1.5);
+        let commands = [
+            Command::Hello { revision: PROTOCOL_REVISION },
+            Command::HelloAck { revision: PROTOCOL_REVISION, build_id: 0xdeadbeef, modes: 0x1ff },
+            Command::EXIT,
+            Command::KeepAlive,
+            Command::ModeChange { mode: Mode::Height },
+            Command::YawPSet { num: 1 },
+            Command::YawDSet { num: -2 },
+            Command::PitchPSet { num: 3 },
+            Command::PitchDSet { num: -4 },
+            Command::RollPSet { num: 5 },
+            Command::RollDSet { num: -6 },
+            Command::Time { num: u128::MAX },
+            Command::ThrottleSet { num: -400 },
+            Command::YawBack { num: 7 },
+            Command::PitchBack { num: -8 },
+            Command::RollBack { num: 9 },
+            Command::ThrottleBack { num: -10 },
+            Command::YawSet { num: 11 },
+            Command::PitchSet { num: -12 },
+            Command::RollSet { num: 13 },
+            Command::Speed { num: -14 },
+            Command::Datalog { mode: Mode::Raw, ypr: [0.1, 0.2, 0.3], raw_ypr: [-0.1, -0.2, -0.3],
+                motor: [1, 2, 3, 4], time: 123456, raw_speed: 0.5, tick: 99 },
+            Command::Motor { motor1: 1, motor2: 2, motor3: 3, motor4: 4 },
+            Command::LinkHealth { stats: LinkStats { frames: 10, checksum_mismatch: 2, ..LinkStats::default() } },
+            Command::Ack { seq: 255 },
+            Command::Nack { seq: 0 },
+            Command::Telemetry { data: Telemetry { tick: 5, mode: Mode::FullControl, ..Telemetry::default() } },
+            Command::TelemetryPeriod { ticks: 20 },
+            Command::ParamSet { id: 3, value },
+            Command::ParamGet { id: 4 },
+            Command::ParamList,
+            Command::ParamValue { id: 5, value },
+            Command::ParamInfo { info: ParamInfo::new(6, "yaw_p", value, -value, value) },
+            Command::LinkFec { enabled: true },
+            Command::TimeSyncRequest { pc_time_us: 1 << 40 },
+            Command::TimeSyncReply { pc_time_us: 1 << 40, drone_time_us: 77, tick: 8 },
+            Command::ConfigSave,
+            Command::ConfigLoad,
+            Command::ConfigReset,
+        ];
+        for (index, command) in commands.iter().enumerate() {
+            assert_eq!(wire_index(command) as usize, index, "{command:?} is listed out of order");
+            let bytes = command.serialize();
+            // postcard writes the index as a varint, a single byte below 128
+            assert_eq!(bytes[0], wire_index(command), "{command:?} moved on the wire");
+            assert_eq!(&from_bytes::<Command>(&bytes).unwrap(), command);
+            assert_eq!(Message::get_message(&serialize_message(command.clone())).as_ref(), Ok(command));
+        }
+    }
+
+    #[test]
+    fn every_bit_error_is_rejected() {
+        let command = Command::Telemetry { data: Telemetry::default() };
+        for frame in [serialize_message(command.clone()), serialize_message_seq(command.clone(), 7)] {
+            for bit in 0..frame.len() * 8 {
+                let mut corrupted = frame.clone();
+                corrupted[bit / 8] ^= 1 << (bit % 8);
+                // a lost delimiter merges the frame with the next one, the one after that gets through
+                let mut decoder = FrameDecoder::new();
+                let mut results = decoder.feed(&corrupted);
+                results.extend(decoder.feed(&serialize_message(Command::KeepAlive).repeat(2)));
+                let last = results.pop().unwrap();
+                assert_eq!(last.map(Message::into_command), Ok(Command::KeepAlive));
+                for message in results.iter().flatten() {
+                    assert_eq!(*message.command(), Command::KeepAlive, "bit {bit} got through");
+                }
+                let stats = decoder.stats();
+                assert!(stats.errors() >= 1, "bit {bit} was not counted");
+                assert_eq!(stats.errors() + stats.frames, results.len() as u32 + 1);
+            }
+        }
+    }
+}
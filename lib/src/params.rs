@@ -0,0 +1,30 @@
+use serde::{Serialize, Deserialize};
+use fixed::types::I22F10;
+
+// Longest parameter name, shorter names are padded with zeros
+pub const PARAM_NAME_LEN: usize = 16;
+
+/// Description of one tunable parameter of the drone, sent in answer to `ParamList`.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+pub struct ParamInfo {
+    pub id: u8,
+    name: [u8; PARAM_NAME_LEN],
+    pub value: I22F10,
+    pub min: I22F10,
+    pub max: I22F10,
+}
+
+impl ParamInfo {
+    /// `name` is cut off after `PARAM_NAME_LEN` bytes
+    pub fn new(id: u8, name: &str, value: I22F10, min: I22F10, max: I22F10) -> Self {
+        let mut bytes = [0; PARAM_NAME_LEN];
+        let len = name.len().min(PARAM_NAME_LEN);
+        bytes[..len].copy_from_slice(&name.as_bytes()[..len]);
+        ParamInfo { id, name: bytes, value, min, max }
+    }
+
+    pub fn name(&self) -> &str {
+        let len = self.name.iter().position(|&b| b == 0).unwrap_or(PARAM_NAME_LEN);
+        core::str::from_utf8(&self.name[..len]).unwrap_or("?")
+    }
+}
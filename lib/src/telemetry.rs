@@ -0,0 +1,30 @@
+use serde::{Serialize, Deserialize};
+use crate::Mode;
+
+// Ticks between two telemetry frames until the pc asks for another rate
+pub const DEFAULT_TELEMETRY_PERIOD: u16 = 5;
+
+/// Snapshot of the drone state, sent as one frame so all fields belong to the same tick.
+///
+/// Angles, setpoints, height and vertical speed are the bits of an `I22F10`.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug, Default)]
+pub struct Telemetry {
+    /// control loop tick the snapshot was taken in
+    pub tick: u32,
+    pub mode: Mode,
+    /// yaw, pitch and roll estimate in radians
+    pub attitude: [i32; 3],
+    /// yaw, pitch and roll reference from the joystick
+    pub setpoint: [i32; 3],
+    pub throttle: i16,
+    pub motors: [u16; 4],
+    /// battery voltage in centivolts
+    pub battery: u16,
+    /// altitude above the calibration point from the barometer and accelerometer, in m
+    pub height: i32,
+    /// vertical speed in m/s, up is positive
+    pub vertical_speed: i32,
+    /// how much the mixer changed the thrust, and left out of the roll and pitch and of the yaw,
+    /// to keep the motors within their limits, in percent
+    pub saturation: [u8; 3],
+}
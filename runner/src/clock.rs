@@ -0,0 +1,109 @@
+use std::fmt;
+use std::time::Instant;
+use share_lib::Command;
+
+// Time between two synchronisation requests
+pub const SYNC_INTERVAL_MS: u128 = 1000;
+// Number of recent exchanges the estimate is picked from
+const WINDOW: usize = 8;
+
+/// One completed request/reply exchange
+#[derive(Clone, Copy, Debug)]
+pub struct SyncSample {
+    /// round-trip time of the exchange
+    pub rtt_us: u64,
+    /// drone time minus pc time
+    pub offset_us: i64,
+    /// drone time and tick at which the drone answered
+    pub drone_time_us: u64,
+    pub tick: u32,
+}
+
+/// Estimates the offset between the drone clock and the pc clock from
+/// `TimeSyncRequest`/`TimeSyncReply` exchanges.
+///
+/// The drone is assumed to answer halfway through the round trip. Of the last
+/// `WINDOW` exchanges the one with the shortest round trip is used, since it had
+/// the least room for queueing delay.
+pub struct ClockSync {
+    epoch: Instant,
+    last_request: Option<Instant>,
+    samples: Vec<SyncSample>,
+}
+
+impl ClockSync {
+    pub fn new() -> Self {
+        ClockSync {
+            epoch: Instant::now(),
+            last_request: None,
+            samples: Vec::new(),
+        }
+    }
+
+    /// Microseconds since the runner started, the pc time used in the exchange.
+    pub fn pc_time_us(&self, now: Instant) -> u64 {
+        now.duration_since(self.epoch).as_micros() as u64
+    }
+
+    /// Returns a new request if the last one is `SYNC_INTERVAL_MS` old.
+    ///
+    /// # Parameters
+    ///
+    /// * `now` - The time at which the request is written to the serial port.
+    pub fn poll(&mut self, now: Instant) -> Option<Command> {
+        if self.last_request.is_some_and(|t| now.duration_since(t).as_millis() < SYNC_INTERVAL_MS) {
+            return None;
+        }
+        self.last_request = Some(now);
+        Some(Command::TimeSyncRequest { pc_time_us: self.pc_time_us(now) })
+    }
+
+    /// Handles a `TimeSyncReply`.
+    ///
+    /// # Parameters
+    ///
+    /// * `pc_time_us` - The pc time of the request, echoed by the drone.
+    /// * `drone_time_us` - The drone time at which the drone answered.
+    /// * `tick` - The control loop tick at which the drone answered.
+    /// * `now` - The time at which the reply was received.
+    ///
+    /// # Returns
+    ///
+    /// Returns the sample of this exchange.
+    pub fn reply(&mut self, pc_time_us: u64, drone_time_us: u64, tick: u32, now: Instant) -> SyncSample {
+        let received_us = self.pc_time_us(now);
+        let rtt_us = received_us.saturating_sub(pc_time_us);
+        let midpoint_us = pc_time_us + rtt_us / 2;
+        let sample = SyncSample {
+            rtt_us,
+            offset_us: drone_time_us as i64 - midpoint_us as i64,
+            drone_time_us,
+            tick,
+        };
+        if self.samples.len() == WINDOW {
+            self.samples.remove(0);
+        }
+        self.samples.push(sample);
+        sample
+    }
+
+    /// The sample with the shortest round trip among the recent exchanges
+    pub fn estimate(&self) -> Option<SyncSample> {
+        self.samples.iter().min_by_key(|s| s.rtt_us).copied()
+    }
+
+    /// Converts a drone time to pc time, if an estimate is available
+    pub fn to_pc_us(&self, drone_time_us: u64) -> Option<i64> {
+        self.estimate().map(|s| drone_time_us as i64 - s.offset_us)
+    }
+}
+
+/// The clock line of `output.txt`
+impl fmt::Display for ClockSync {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.estimate() {
+            Some(sample) => write!(f, "clock: offset {} us rtt {} us", sample.offset_us, sample.rtt_us),
+            None => write!(f, "clock: not synchronised"),
+        }
+    }
+}
@@ -1,14 +1,30 @@
+use std::collections::BTreeMap;
 use std::fs::{File, OpenOptions};
 use std::io:: Write;
-use share_lib::{Command, GyroValue, Mode, YPRT};
+use std::time::Instant;
+use share_lib::{Command, GyroValue, LinkStats, Mode, ParamInfo, PROTOCOL_REVISION, Telemetry, DEFAULT_TELEMETRY_PERIOD, YPRT};
 // use clearscreen;
 use crate::joystick::Joystick;
+use crate::clock::ClockSync;
+use crate::reliable::{Outcome, ReliableSender};
 use fixed::types::I22F10;
 
+/// State of the version handshake with the firmware
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Handshake {
+    /// no HelloAck received yet
+    Pending,
+    /// the firmware speaks the same protocol revision
+    Matched { build_id: u32, modes: u16 },
+    /// the firmware was built against another protocol revision
+    Mismatch { revision: u16 },
+}
+
 pub struct Interface {
     // Fields of the struct
     pub current_mode: Mode,
-    pub height:i32,
+    pub height:f32,
+    pub vertical_speed:f32,
     pub idle: bool,
     pub js: Joystick,
     pub configure: YPRT,
@@ -21,6 +37,17 @@ pub struct Interface {
     pub work_flag:bool,
     pub battery: u16,
     pub velocity: i32,
+    pub link_pc: LinkStats, // frames decoded by the pc
+    pub link_drone: LinkStats, // frames decoded by the drone, as last reported
+    pub reliable: ReliableSender, // commands waiting for an Ack
+    pub rejected: String, // last command the drone rejected or did not answer
+    pub exiting: bool,
+    pub handshake: Handshake,
+    pub telemetry: Telemetry, // last complete snapshot from the drone
+    pub telemetry_period: u16, // requested ticks between telemetry frames
+    pub params: BTreeMap<u8, ParamInfo>, // parameters of the drone by id, filled by ParamList
+    pub link_fec: bool, // drone asked to LDPC encode telemetry and log dumps
+    pub clock: ClockSync, // offset between the drone clock and ours
 }
 
 impl Interface {
@@ -30,7 +57,8 @@ impl Interface {
         file.set_len(0).expect("fail reset file data");
         Interface{
             current_mode: Mode::Safe,
-            height:0,
+            height:0.0,
+            vertical_speed:0.0,
             idle: false,
             abort: false,
             js: Joystick::new(),
@@ -42,7 +70,18 @@ impl Interface {
             pid_roll:[0,0,0],
             work_flag:true,
             battery:0,
-            velocity:0
+            velocity:0,
+            link_pc: LinkStats::new(),
+            link_drone: LinkStats::new(),
+            reliable: ReliableSender::new(),
+            rejected: String::new(),
+            exiting: false,
+            handshake: Handshake::Pending,
+            telemetry: Telemetry::default(),
+            telemetry_period: DEFAULT_TELEMETRY_PERIOD,
+            params: BTreeMap::new(),
+            link_fec: false,
+            clock: ClockSync::new(),
         }
     }
     /// Processes various commands to manipulate drone states or configurations.
@@ -66,27 +105,6 @@ impl Interface {
             Command::Motor { motor1, motor2, motor3, motor4 } => {
                 self.motor = [motor1, motor2, motor3, motor4];
             }
-            Command::Motor1 { num} => {
-                self.motor[0] = num;
-            }
-            Command::Motor2 { num} => {
-                self.motor[1] = num;
-            }
-            Command::Motor3 { num} => {
-                self.motor[2] = num;
-            }
-            Command::Motor4 { num} => {
-                self.motor[3] = num;
-            }
-            Command::Trueyaw {num} =>{
-                self.yprt.yaw =num;
-            }
-            Command::Truepitch {num} =>{
-                self.yprt.pitch = num;
-            }
-            Command::Trueroll{num}=>{
-                self.yprt.roll = num;
-            }
             Command::YawSet {num}=>{
                 self.yprt.yaw = num;
             }
@@ -128,11 +146,56 @@ impl Interface {
                 println!("t:{}",t);
                 self.velocity= num;
             }
-            Command::BatteryCheck {num}=>{
-                self.battery = num;
+            Command::LinkHealth {stats}=>{
+                self.link_drone = stats;
             }
-            Command::Height {num}=>{
-                self.height = I22F10::from_bits(num).to_num();
+            Command::Ack {seq}=>{
+                if let Some(outcome) = self.reliable.answer(seq, true) {
+                    self.report(outcome);
+                }
+            }
+            Command::Nack {seq}=>{
+                if let Some(outcome) = self.reliable.answer(seq, false) {
+                    self.report(outcome);
+                }
+            }
+            Command::HelloAck {revision, build_id, modes}=>{
+                if revision == PROTOCOL_REVISION {
+                    self.handshake = Handshake::Matched { build_id, modes };
+                } else {
+                    if self.handshake != (Handshake::Mismatch { revision }) {
+                        println!("firmware speaks protocol revision {}, expected {}; arming disabled",
+                                 revision, PROTOCOL_REVISION);
+                    }
+                    self.handshake = Handshake::Mismatch { revision };
+                }
+            }
+            Command::Telemetry {data}=>{
+                //all fields come from the same tick, so they are taken over together
+                self.telemetry = data;
+                self.yprt.yaw = data.attitude[0];
+                self.yprt.pitch = data.attitude[1];
+                self.yprt.roll = data.attitude[2];
+                self.motor = data.motors;
+                self.battery = data.battery;
+                self.height = I22F10::from_bits(data.height).to_num();
+                self.vertical_speed = I22F10::from_bits(data.vertical_speed).to_num();
+            }
+            Command::ParamInfo {info}=>{
+                self.params.insert(info.id, info);
+                self.print_params();
+            }
+            Command::ParamValue {id, value}=>{
+                match self.params.get_mut(&id) {
+                    Some(info) => info.value = value,
+                    None => println!("param {} = {}", id, value),
+                }
+                self.print_params();
+            }
+            Command::TimeSyncReply {pc_time_us, drone_time_us, tick}=>{
+                let sample = self.clock.reply(pc_time_us, drone_time_us, tick, Instant::now());
+                // every exchange is kept so logs can be merged afterwards
+                append_data(&format!("sync:{},{},{},{}\n", pc_time_us, sample.drone_time_us, sample.tick, sample.rtt_us));
             }
             Command::Datalog{
                 mode,
@@ -140,15 +203,15 @@ impl Interface {
                 raw_ypr,
                 motor,
                 time,
-                raw_speed
+                raw_speed,
+                tick
             }=>{
                 println!("get log");
-                let mut file = OpenOptions::new()
-                    .write(true)
-                    .append(true)
-                    .create(true)
-                    .open("drone_data.txt").expect("open data file fail");
-
+                // record time on the pc clock, when the clocks have been synchronised
+                let pc_time = match self.clock.to_pc_us(time as u64 * 1000) {
+                    Some(us) => us.to_string(),
+                    None => String::from("-"),
+                };
                 let output = format!(
                     "mode:{}\n\
                     ypr:{},{},{}\n\
@@ -156,20 +219,102 @@ impl Interface {
                      motor:{},{},{},{}\n\
                      time:{}\n\
                      raw_speed:{}\n\
+                     tick:{}\n\
+                     pc_time_us:{}\n\
                      ",mode_format(mode),ypr[0],ypr[1],ypr[2],raw_ypr[0],raw_ypr[1],raw_ypr[2],
                     motor[0],motor[1],motor[2],motor[3],
-                    time,raw_speed
+                    time,raw_speed,tick,pc_time
                 );
-
-                match file.write_all(output.as_bytes()) {
-                    Ok(_) => {},
-                    Err(e) => eprintln!("write fail : {}", e),
-                }
+                append_data(&output);
             }
             _ => {
             }
         }
     }
+    /// Serializes a command for the drone, refusing mode changes the firmware has not
+    /// confirmed it supports. Until a matching HelloAck arrives only Safe and Panic can be entered.
+    ///
+    /// # Parameters
+    ///
+    /// * `cmd` - The command to be sent to the drone.
+    /// * `now` - The time at which the returned bytes are written to the serial port.
+    ///
+    /// # Returns
+    ///
+    /// Returns the bytes of the message, or nothing if the command was refused.
+    pub fn send(&mut self, cmd: Command, now: Instant) -> Vec<u8> {
+        if !self.command_allowed(&cmd) {
+            let refused = format!("refused: {:?}, handshake {:?}", cmd, self.handshake);
+            // the joystick repeats its commands, report them once
+            if refused != self.rejected {
+                println!("{}", refused);
+                self.rejected = refused;
+            }
+            return Vec::new();
+        }
+        self.reliable.frame(cmd, now)
+    }
+
+    /// Serializes a list of commands with `send`.
+    pub fn send_all(&mut self, cmds: Vec<Command>, now: Instant) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for cmd in cmds {
+            bytes.extend(self.send(cmd, now));
+        }
+        bytes
+    }
+
+    /// Returns `true` if `cmd` may be sent given the outcome of the handshake. Until the firmware
+    /// is known to speak the same protocol only the handshake and the way back to safe go out.
+    pub fn command_allowed(&self, cmd: &Command) -> bool {
+        match cmd {
+            Command::Hello { .. } => true,
+            Command::ModeChange { mode } => self.mode_allowed(*mode),
+            _ => matches!(self.handshake, Handshake::Matched { .. }),
+        }
+    }
+
+    /// Returns `true` if the drone may be put in `mode` given the outcome of the handshake
+    pub fn mode_allowed(&self, mode: Mode) -> bool {
+        match (mode, self.handshake) {
+            (Mode::Safe, _) | (Mode::Panic, _) => true,
+            (_, Handshake::Matched { modes, .. }) => modes & mode.bit() != 0,
+            _ => false,
+        }
+    }
+
+    /// Records the outcome of an acknowledged command so it can be shown in the interface.
+    ///
+    /// # Parameters
+    ///
+    /// * `outcome` - What happened to the command: accepted, rejected by the drone or not answered.
+    pub fn report(&mut self, outcome: Outcome) {
+        match outcome {
+            Outcome::Accepted(Command::EXIT) => println!("drone acknowledged exit"),
+            Outcome::Accepted(_) => {}
+            Outcome::Rejected(cmd) => {
+                self.rejected = format!("rejected: {:?}", cmd);
+                println!("{}", self.rejected);
+            }
+            Outcome::TimedOut(cmd) => {
+                self.rejected = format!("no answer: {:?}", cmd);
+                println!("{}", self.rejected);
+            }
+        }
+    }
+
+    /// Writes the known parameters of the drone to `params.txt`, one per line as
+    /// `id name value [min, max]`.
+    pub fn print_params(&self) {
+        let mut output = String::new();
+        for info in self.params.values() {
+            output += &format!("{} {} {} [{}, {}]\n", info.id, info.name(), info.value, info.min, info.max);
+        }
+        if let Err(e) = std::fs::write("params.txt", output) {
+            eprintln!("write fail : {}", e);
+        }
+    }
+
     fn mode_to_str(&self) -> &str {
         match self.current_mode {
             Mode::Safe => "Safe",
@@ -238,6 +383,12 @@ impl Interface {
              {}\n\
              {}\n\
              {}\n\
+             {:.2} {:+.2}\n\
+             {}\n\
+             {}\n\
+             {}\n\
+             {}\n\
+             telemetry: tick {} every {} ticks, saturation thrust {}% roll/pitch {}% yaw {}%\n\
              {}",
             self.mode_to_str(),
             self.idle,
@@ -247,7 +398,13 @@ impl Interface {
             self.js.y_trim, self.js.p_trim, self.js.r_trim, self.js.t_trim,
             self.motor[0], self.motor[1], self.motor[2], self.motor[3],self.pid_yaw[0],self.pid_yaw[1]
             ,self.pid_pitch[0],self.pid_pitch[1],self.pid_roll[0],self.pid_roll[1],
-            self.battery,self.height
+            self.battery, self.height, self.vertical_speed,
+            link_format(&self.link_pc), link_format(&self.link_drone),
+            self.rejected,
+            handshake_format(&self.handshake),
+            self.telemetry.tick, self.telemetry_period,
+            self.telemetry.saturation[0], self.telemetry.saturation[1], self.telemetry.saturation[2],
+            self.clock
         );
         // write packages to txt file
         match file.write_all(output.as_bytes()) {
@@ -329,4 +486,65 @@ pub fn mode_format(mode:Mode) -> String {
         Mode::Height => "Height".to_string(),
         Mode::LogOut => "LogOut".to_string()
     }
-}
\ No newline at end of file
+}
+
+/// Formats link statistics as a single line for the interface file.
+///
+/// # Parameters
+///
+/// * `stats` - The frame and error counters of one side of the link.
+///
+/// # Returns
+///
+/// Returns a `String` with the number of good frames followed by the count of each error class.
+pub fn link_format(stats: &LinkStats) -> String {
+    format!(
+        "frames:{} delimiter:{} length:{} checksum:{} deserialize:{} version:{} uncorrectable:{}",
+        stats.frames, stats.bad_delimiter, stats.length_mismatch,
+        stats.checksum_mismatch, stats.deserialize, stats.unknown_version, stats.uncorrectable
+    )
+}
+
+/// Appends lines to `drone_data.txt`
+fn append_data(output: &str) {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .append(true)
+        .create(true)
+        .open("drone_data.txt").expect("open data file fail");
+
+    match file.write_all(output.as_bytes()) {
+        Ok(_) => {},
+        Err(e) => eprintln!("write fail : {}", e),
+    }
+}
+
+/// Formats the handshake state as one line of `output.txt`
+pub fn handshake_format(handshake: &Handshake) -> String {
+    match handshake {
+        Handshake::Pending => String::from("firmware: waiting for handshake"),
+        Handshake::Matched { build_id, .. } => format!("firmware: rev {} build {:08x}", PROTOCOL_REVISION, build_id),
+        Handshake::Mismatch { revision } => format!("firmware: rev {} != {}, arming disabled", revision, PROTOCOL_REVISION),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_way_to_safe_goes_out_before_the_handshake() {
+        let mut interface = Interface::new();
+        let now = Instant::now();
+        assert!(interface.send(Command::ThrottleSet { num: -100 }, now).is_empty());
+        assert!(interface.send(Command::ParamGet { id: 0 }, now).is_empty());
+        assert!(interface.send(Command::ModeChange { mode: Mode::Manual }, now).is_empty());
+        assert!(!interface.send(Command::ModeChange { mode: Mode::Panic }, now).is_empty());
+        assert!(!interface.send(Command::ModeChange { mode: Mode::Safe }, now).is_empty());
+        assert!(!interface.send(Command::Hello { revision: PROTOCOL_REVISION }, now).is_empty());
+
+        interface.handshake = Handshake::Matched { build_id: 0, modes: Mode::Manual.bit() };
+        assert!(!interface.send(Command::ThrottleSet { num: -100 }, now).is_empty());
+        assert!(!interface.send(Command::ModeChange { mode: Mode::Manual }, now).is_empty());
+    }
+}
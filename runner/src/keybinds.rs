@@ -1,6 +1,9 @@
 use share_lib::Mode;
 use crate::interface::{check_js, Interface};
 use fixed::types::I22F10;
+
+// Slowest telemetry rate that can be selected, in ticks between frames
+const MAX_TELEMETRY_PERIOD: u16 = 100;
 /// Maps keyboard inputs to corresponding drone control commands.
 ///
 /// # Parameters
@@ -21,7 +24,8 @@ pub fn key_to_cmd(key: termion::event::Key, interface: &mut Interface) -> Option
             Some(share_lib::Command::ModeChange {mode: Mode::Panic})
         },
         termion::event::Key::Char('g')=> {
-            interface.work_flag = false;
+            // quit once the panic has been acknowledged
+            interface.exiting = true;
             Some(share_lib::Command::ModeChange {mode: Mode::Panic})
         },
         termion::event::Key::Char('0') => {
@@ -47,12 +51,14 @@ pub fn key_to_cmd(key: termion::event::Key, interface: &mut Interface) -> Option
             } else { None }
         },
         termion::event::Key::Char('5') => {
-            if check_js(&interface) && interface.current_mode == Mode::Safe{
+            // switching altitude hold off in flight keeps the throttle where it is
+            if (check_js(&interface) && interface.current_mode == Mode::Safe) || interface.current_mode == Mode::Height {
                 Some(share_lib::Command::ModeChange {mode: Mode::FullControl})
             } else { None }
         },
         termion::event::Key::Char('6') => {
-            if check_js(&interface) && interface.current_mode == Mode::Safe{
+            // altitude hold can be switched on in flight
+            if (check_js(&interface) && interface.current_mode == Mode::Safe) || interface.current_mode == Mode::FullControl {
                 Some(share_lib::Command::ModeChange {mode: Mode::Height})
             } else { None }
         },
@@ -126,6 +132,35 @@ pub fn key_to_cmd(key: termion::event::Key, interface: &mut Interface) -> Option
             Some(share_lib::Command::RollDSet {num:interface.pid_roll[1]-1})
             // Some(share_lib::Command::DControlDown)
         },
+        // Telemetry rate: '[' halves it, ']' doubles it
+        termion::event::Key::Char('[') => {
+            interface.telemetry_period = (interface.telemetry_period * 2).min(MAX_TELEMETRY_PERIOD);
+            Some(share_lib::Command::TelemetryPeriod {ticks: interface.telemetry_period})
+        },
+        termion::event::Key::Char(']') => {
+            interface.telemetry_period = (interface.telemetry_period / 2).max(1);
+            Some(share_lib::Command::TelemetryPeriod {ticks: interface.telemetry_period})
+        },
+        // Ask the drone for all of its parameters
+        termion::event::Key::Char('p') => {
+            Some(share_lib::Command::ParamList)
+        },
+        // Toggle forward error correction on telemetry and log dumps
+        termion::event::Key::Char('f') => {
+            interface.link_fec = !interface.link_fec;
+            Some(share_lib::Command::LinkFec {enabled: interface.link_fec})
+        },
+        // Keep the parameters and calibration in the flash of the drone, only in safe mode
+        termion::event::Key::Char('s') => {
+            Some(share_lib::Command::ConfigSave)
+        },
+        termion::event::Key::Char('r') => {
+            Some(share_lib::Command::ConfigLoad)
+        },
+        // Back to the defaults, capital so it is not hit by accident
+        termion::event::Key::Char('R') => {
+            Some(share_lib::Command::ConfigReset)
+        },
         // Else print the pressed key
         _ => {
             None
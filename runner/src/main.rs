@@ -2,12 +2,12 @@ use std::env::args;
 use tudelft_serial_upload::{upload_file_or_stop, PortSelector};
 use tudelft_serial_upload::serial2::SerialPort;
 use std::{thread, time};
-use share_lib::{Message, Mode, Command, serialize_message, serialize_messages};
+use share_lib::{FrameDecoder, Mode, Command, serialize_message, PROTOCOL_REVISION};
 use termion;
 use termion::input::TermRead;
 use std::time::{Duration, Instant};
 use crate::keybinds::key_to_cmd;
-use crate::interface::Interface;
+use crate::interface::{Handshake, Interface};
 use crate::interface::check_js;
 use std::env;
 use gilrs::Gilrs;
@@ -16,12 +16,17 @@ mod interface;
 mod joystick;
 mod gui;
 mod threads;
+mod reliable;
+mod clock;
 
 use std::sync::{Arc, mpsc, Mutex};
 use share_lib::Command::ModeChange;
 use share_lib::Mode::Manual;
 use crate::threads::{gui_threads, key_threads};
 
+// Time between two Hello messages while the firmware has not answered the handshake
+const HELLO_RETRY_MS: u128 = 500;
+
 fn main() {
     env::set_var("RUST_BACKTRACE", "1");
     // get a filename from the command line. This filename will be uploaded to the drone
@@ -56,6 +61,7 @@ fn main() {
 
     let mut last = Instant::now();
     let mut last_keepalive = Instant::now();
+    let mut last_hello: Option<Instant> = None;
     let  stdin = termion::async_stdin().keys();
 
     let mut gilrs = Gilrs::new().unwrap();
@@ -64,10 +70,7 @@ fn main() {
     let stdout = py_process.stdout.take().unwrap();
     //spawn thread gui
     let _gui_handler = gui_threads(stdout,gui_tx);
-    let mut real_buf = [0; 128];
-    let mut count = 0;
-    let mut start_flag = false;
-    let mut len = 0;
+    let mut decoder = FrameDecoder::new();
     //spawn thread keyboard
     let _key_handler = key_threads(stdin,key_tx);
 
@@ -83,36 +86,12 @@ fn main() {
         let dt_keepalive = now.duration_since(last_keepalive);
         if let Ok(num) = serial_for_thread.lock().unwrap().read(&mut buf) {
             if num > 0 { // Serial communication, bytes received
-                let buffer_cpy = buf.clone();
-                for buf_idx in 0..num {
-                    if start_flag{
-                        len = buffer_cpy[buf_idx] as usize;
-                        start_flag = false;
-                    }
-                    if buffer_cpy[buf_idx] == 0xFE {
-                        count = 0;
-                        start_flag = true;
-                    }
-                    if count > 127 {
-                        count = 0;
-                    }
-                    real_buf[count] =buffer_cpy[buf_idx];
-                    count += 1;
-                    if buffer_cpy[buf_idx] == 0xFF && count > 2 && (len+5) == count {
-                        // println!("{:?}",&real_buf[0..count]);
-                        match Message::get_message(&real_buf[0..count]) {
-
-                            Some(cmd) => {
-                                last = now;
-                                interface.idle = false;
-                                interface.process_command(cmd);
-                            }
-                            None => {
-                            }
-                        };
-                        count = 0;
-                    }
+                for message in decoder.feed(&buf[..num]).into_iter().flatten() {
+                    last = now;
+                    interface.idle = false;
+                    interface.process_command(message.into_command());
                 }
+                interface.link_pc = decoder.stats();
             } else {
                 // Serial communication but no bytes???
             }
@@ -133,11 +112,11 @@ fn main() {
         // interface.js.read_joystick(&mut gilrs);
         match interface.js.read_joystick(&mut gilrs) {
             Some(cmd) => {
-                mes_package.extend(serialize_messages(cmd));
+                mes_package.extend(interface.send_all(cmd, now));
                 //correct value
                 match interface.correct_ref(){
                     None => {}
-                    Some(cmd) => {mes_package.extend(serialize_messages(cmd));}
+                    Some(cmd) => {mes_package.extend(interface.send_all(cmd, now));}
                 };
             }
             None => {
@@ -150,42 +129,46 @@ fn main() {
         match gui_rx.try_recv() {
             Ok(cmd) => {
                 match cmd {
-                    Command::EXIT =>{interface.work_flag = false}
+                    Command::EXIT =>{
+                        //leave once the drone has acknowledged or stopped answering
+                        interface.exiting = true;
+                        mes_package.extend(interface.send(cmd, now));
+                    }
                     ModeChange { mode: Manual } => {
                         if check_js(&interface) {
-                            mes_package.extend(serialize_message(cmd));
+                            mes_package.extend(interface.send(cmd, now));
                         }
                     },
                     ModeChange { mode: Mode::Panic } => {
                         interface.js.throttle = 0;
-                        mes_package.extend(serialize_message(cmd));
+                        mes_package.extend(interface.send(cmd, now));
                     },
                     ModeChange { mode: Mode::Safe } => {
                         interface.js.throttle = 0;
-                        mes_package.extend(serialize_message(cmd));
+                        mes_package.extend(interface.send(cmd, now));
                     },
                     ModeChange { mode: Mode::YawControlled } => {
                         if check_js(&interface) {
-                            mes_package.extend(serialize_message(cmd));
+                            mes_package.extend(interface.send(cmd, now));
                         }
                     },
                     ModeChange { mode: Mode::Calibration } => {
                         if check_js(&interface) {
-                            mes_package.extend(serialize_message(cmd));
+                            mes_package.extend(interface.send(cmd, now));
                         }
                     },
                     ModeChange { mode: Mode::FullControl } => {
                         if check_js(&interface) {
-                            mes_package.extend(serialize_message(cmd));
+                            mes_package.extend(interface.send(cmd, now));
                         }
                     },
                     ModeChange { mode: Mode::Raw } => {
                         if check_js(&interface) {
-                            mes_package.extend(serialize_message(cmd));
+                            mes_package.extend(interface.send(cmd, now));
                         }
                     },
                     _ => {
-                        mes_package.extend(serialize_message(cmd));
+                        mes_package.extend(interface.send(cmd, now));
                     }
                 }
             }
@@ -198,7 +181,7 @@ fn main() {
             Ok(key) =>{
                 match key_to_cmd(key,&mut interface) {
                     Some(cmd) => {
-                        mes_package.extend(serialize_message(cmd));
+                        mes_package.extend(interface.send(cmd, now));
                     },
                     None => { // No next terminal stdin?
                         /*
@@ -214,8 +197,29 @@ fn main() {
         //correct value
         match interface.correct_ref(){
             None => {}
-            Some(cmd) => {mes_package.extend(serialize_messages(cmd));}
+            Some(cmd) => {mes_package.extend(interface.send_all(cmd, now));}
         };
+        //resend acknowledged commands that got no answer
+        let (resend, timed_out) = interface.reliable.poll(now);
+        mes_package.extend(resend);
+        for outcome in timed_out {
+            interface.report(outcome);
+        }
+        if interface.exiting && interface.reliable.is_idle() {
+            interface.work_flag = false;
+        }
+        //ask for the protocol revision of the firmware until it answers
+        if interface.handshake == Handshake::Pending
+            && last_hello.is_none_or(|t| now.duration_since(t).as_millis() > HELLO_RETRY_MS) {
+            last_hello = Some(now);
+            mes_package.extend(serialize_message(Command::Hello { revision: PROTOCOL_REVISION }));
+        }
+        //keep the clock estimate fresh once the firmware is known to understand the exchange
+        if let Handshake::Matched { .. } = interface.handshake {
+            if let Some(cmd) = interface.clock.poll(now) {
+                mes_package.extend(serialize_message(cmd));
+            }
+        }
         //check alive
         if dt_keepalive.as_millis() > share_lib::KEEPALIVE_TX_MS {
             last_keepalive = now;
@@ -0,0 +1,145 @@
+use std::time::Instant;
+use share_lib::{Command, serialize_message, serialize_message_seq};
+
+// Time to wait for an Ack before sending a command again
+pub const RETRANSMIT_MS: u128 = 150;
+// Number of times a command is sent before it is given up on
+pub const MAX_TRIES: u8 = 5;
+
+struct Pending {
+    seq: u8,
+    cmd: Command,
+    sent: Instant,
+    tries: u8,
+}
+
+/// Returns `true` for the commands that put the drone in another mode
+fn changes_mode(cmd: &Command) -> bool {
+    matches!(cmd, Command::ModeChange { .. } | Command::EXIT)
+}
+
+/// Result of an acknowledged command that is no longer waiting for an answer
+pub enum Outcome {
+    Accepted(Command),
+    Rejected(Command),
+    TimedOut(Command),
+}
+
+/// Keeps track of commands that have to be acknowledged by the drone and
+/// resends them until an Ack or Nack with their sequence number comes back.
+pub struct ReliableSender {
+    next_seq: u8,
+    pending: Vec<Pending>,
+}
+
+impl ReliableSender {
+    pub fn new() -> Self {
+        ReliableSender {
+            next_seq: 0,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Serializes a command, giving it a sequence number if it needs to be acknowledged.
+    /// A mode command drops the mode commands that are still waiting for an answer.
+    ///
+    /// # Parameters
+    ///
+    /// * `cmd` - The command to be sent to the drone.
+    /// * `now` - The time at which the returned bytes are written to the serial port.
+    ///
+    /// # Returns
+    ///
+    /// Returns the bytes of the message to be sent.
+    pub fn frame(&mut self, cmd: Command, now: Instant) -> Vec<u8> {
+        if !cmd.needs_ack() {
+            return serialize_message(cmd);
+        }
+        // a mode change still waiting for an answer is not sent again once a newer one is on its way,
+        // a late retransmission could otherwise undo a Panic or Safe
+        if changes_mode(&cmd) {
+            self.pending.retain(|p| !changes_mode(&p.cmd));
+        }
+        let seq = self.next_seq;
+        self.next_seq = self.next_seq.wrapping_add(1);
+        let bytes = serialize_message_seq(cmd.clone(), seq);
+        self.pending.push(Pending { seq, cmd, sent: now, tries: 1 });
+        bytes
+    }
+
+    /// Handles an Ack or Nack from the drone.
+    ///
+    /// # Parameters
+    ///
+    /// * `seq` - The sequence number the drone answered.
+    /// * `accepted` - `true` for an Ack, `false` for a Nack.
+    ///
+    /// # Returns
+    ///
+    /// Returns the outcome of the command with that sequence number, or `None` if it is
+    /// not waiting for an answer (for example because the answer to a retransmission arrived twice).
+    pub fn answer(&mut self, seq: u8, accepted: bool) -> Option<Outcome> {
+        let idx = self.pending.iter().position(|p| p.seq == seq)?;
+        let cmd = self.pending.remove(idx).cmd;
+        if accepted {
+            Some(Outcome::Accepted(cmd))
+        } else {
+            Some(Outcome::Rejected(cmd))
+        }
+    }
+
+    /// Resends every command whose answer is overdue.
+    ///
+    /// # Parameters
+    ///
+    /// * `now` - The current time.
+    ///
+    /// # Returns
+    ///
+    /// Returns the bytes to be sent again, and the commands that were sent `MAX_TRIES`
+    /// times without an answer and are dropped.
+    pub fn poll(&mut self, now: Instant) -> (Vec<u8>, Vec<Outcome>) {
+        let mut bytes = Vec::new();
+        let mut timed_out = Vec::new();
+        let mut idx = 0;
+        while idx < self.pending.len() {
+            let pending = &mut self.pending[idx];
+            if now.duration_since(pending.sent).as_millis() < RETRANSMIT_MS {
+                idx += 1;
+            } else if pending.tries >= MAX_TRIES {
+                timed_out.push(Outcome::TimedOut(self.pending.remove(idx).cmd));
+            } else {
+                pending.tries += 1;
+                pending.sent = now;
+                bytes.extend(serialize_message_seq(pending.cmd.clone(), pending.seq));
+                idx += 1;
+            }
+        }
+        (bytes, timed_out)
+    }
+
+    /// Returns `true` if no command is waiting for an answer
+    pub fn is_idle(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use share_lib::Mode;
+    use super::*;
+
+    #[test]
+    fn newer_mode_change_supersedes_pending_one() {
+        let mut sender = ReliableSender::new();
+        let now = Instant::now();
+        sender.frame(Command::ModeChange { mode: Mode::Manual }, now);
+        sender.frame(Command::ParamGet { id: 0 }, now);
+        sender.frame(Command::YawPSet { num: 3 }, now);
+        sender.frame(Command::ModeChange { mode: Mode::Panic }, now);
+        let pending: Vec<(u8, Command)> = sender.pending.iter().map(|p| (p.seq, p.cmd.clone())).collect();
+        assert_eq!(pending, [(1, Command::YawPSet { num: 3 }), (2, Command::ModeChange { mode: Mode::Panic })]);
+        // the answer to the dropped one is ignored
+        assert!(sender.answer(0, true).is_none());
+    }
+}
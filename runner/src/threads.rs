@@ -9,6 +9,7 @@ use termion::event::Key;
 use termion::input::Keys;
 use termion::raw::IntoRawMode;
 use share_lib::{Command, Mode};
+use fixed::types::I22F10;
 /// Spawns a thread to read lines from a given output and send commands to a GUI via a channel.
 ///
 /// # Parameters
@@ -103,6 +104,23 @@ pub fn gui_threads(stdout:ChildStdout,gui_tx:Sender<Command>) ->JoinHandle<()>{
                                         // println!("{}",val.trim().parse::<i16>().unwrap());
                                     }
                                 }
+                                // param:<id>:<value> sets a parameter, param:<id> reads it back
+                                "param" =>{
+                                    let id = parts.next().and_then(|id| id.trim().parse::<u8>().ok());
+                                    let value = parts.next().and_then(|val| val.trim().parse::<f32>().ok());
+                                    match (id, value) {
+                                        (Some(id), Some(value)) => {
+                                            gui_tx.send(Command::ParamSet {id, value: I22F10::from_num(value)}).expect("gui error");
+                                        }
+                                        (Some(id), None) => {
+                                            gui_tx.send(Command::ParamGet {id}).expect("gui error");
+                                        }
+                                        _ => println!("usage: param:<id>[:<value>]"),
+                                    }
+                                }
+                                "param_list" =>{
+                                    gui_tx.send(Command::ParamList).expect("gui error");
+                                }
                                 _ => {}
                             }
                         }
@@ -0,0 +1,54 @@
+use std::env::args;
+use std::process::{exit, Command};
+use std::thread;
+use std::time::{Duration, Instant};
+use dronecode::control::TICK_FREQ;
+use simulator::pty::Pty;
+use simulator::usb::DronePort;
+use simulator::{QuadParams, SensorNoise, Simulation};
+
+/// Runs the firmware in real time behind a pseudo-terminal.
+///
+/// The runner is started with the terminal standing in for the drone on USB,
+/// so the keyboard, joystick and GUI fly the simulated drone.
+/// Usage: `sim-pty [seed] [-- runner...]`, where the runner defaults to
+/// `cargo run -p runner`.
+fn main() {
+    let mut args = args().skip(1).peekable();
+    let seed: u64 = match args.peek() {
+        Some(arg) if arg != "--" => args.next().unwrap().parse().expect("the seed is a number"),
+        _ => 1,
+    };
+    let mut runner: Vec<String> = args.skip_while(|arg| arg == "--").collect();
+    if runner.is_empty() {
+        runner = ["cargo", "run", "-p", "runner"].map(String::from).to_vec();
+    }
+
+    let mut pty = Pty::open().expect("cannot open a pseudo-terminal");
+    let port = DronePort::new(&pty.path).expect("cannot set up the drone port");
+    eprintln!("simulated drone on {}", pty.path.display());
+    let mut child = port.spawn(Command::new(&runner[0]).args(&runner[1..]))
+        .expect("cannot start the runner with the drone port");
+
+    let mut sim = Simulation::new(QuadParams::default(), SensorNoise::default(), seed);
+    let tick = Duration::from_micros(1_000_000 / TICK_FREQ);
+    let mut next = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait().expect("cannot wait for the runner") {
+            drop(port);
+            exit(status.code().unwrap_or(1));
+        }
+        let received = pty.read_available().expect("cannot read from the terminal");
+        sim.hal_mut().board.rx.extend(received);
+        sim.step();
+        let sent = sim.take_sent();
+        pty.write(&sent).expect("cannot write to the terminal");
+
+        // keep the simulated clock in step with the wall clock
+        next += tick;
+        match next.checked_duration_since(Instant::now()) {
+            Some(wait) => thread::sleep(wait),
+            None => next = Instant::now(),
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use dronecode::hal::{Accel, DroneHal, FlashError, Gyro, Led, MockHal, Quaternion};
+use crate::physics::{QuadParams, QuadState};
+use crate::sensors::Sensors;
+
+// time step of the physics in us
+const PHYSICS_STEP_US: u64 = 1000;
+// processor cycles per us, the nrf51 runs at 16MHz
+const CYCLES_PER_US: u32 = 16;
+
+/// Simple model of the 3S battery: the open circuit voltage drops with the
+/// charge used and the voltage sags with the current the motors draw.
+#[derive(Clone, Copy, Debug)]
+pub struct Battery {
+    /// open circuit voltage when full and when empty in V
+    pub full: f64,
+    pub empty: f64,
+    /// voltage at which a motor turns at the speed it is set to in V, the speed goes with the voltage
+    pub nominal: f64,
+    /// capacity in As
+    pub capacity: f64,
+    /// internal resistance in Ohm
+    pub resistance: f64,
+    /// current drawn per N of thrust in A
+    pub amps_per_newton: f64,
+    /// charge used so far in As
+    pub used: f64,
+    /// current drawn during the last physics step in A
+    pub current: f64,
+}
+
+impl Default for Battery {
+    fn default() -> Self {
+        Battery {
+            full: 12.4,
+            empty: 10.8,
+            nominal: 11.1,
+            capacity: 1300.0 * 3.6,
+            resistance: 0.05,
+            amps_per_newton: 1.0,
+            used: 0.0,
+            current: 0.0,
+        }
+    }
+}
+
+impl Battery {
+    /// Terminal voltage in V
+    pub fn voltage(&self) -> f64 {
+        let charge = (1.0 - self.used / self.capacity).clamp(0.0, 1.0);
+        self.empty + (self.full - self.empty) * charge - self.resistance * self.current
+    }
+}
+
+/// The drone hardware backed by the simulation.
+///
+/// Motors, sensors, the battery and time come from the physics; the uart,
+/// flash and leds are kept in memory by a `MockHal`. Time only moves on
+/// `wait_for_next_tick` and `delay`, and the physics moves along with it.
+pub struct SimHal {
+    pub board: MockHal,
+    pub params: QuadParams,
+    pub state: QuadState,
+    pub sensors: Sensors,
+    pub battery: Battery,
+    time_us: u64,
+    tick_us: u64,
+    // time the physics is behind on
+    pending_us: u64,
+}
+
+impl SimHal {
+    /// # Parameters
+    ///
+    /// * `params` - The physical constants of the drone.
+    /// * `sensors` - The sensor model, which holds the seed of the noise.
+    pub fn new(params: QuadParams, sensors: Sensors) -> Self {
+        SimHal {
+            board: MockHal::new(),
+            params,
+            state: QuadState::landed(),
+            sensors,
+            battery: Battery::default(),
+            time_us: 0,
+            tick_us: 10_000,
+            pending_us: 0,
+        }
+    }
+
+    /// Lets `us` microseconds pass in the simulation.
+    pub fn advance(&mut self, us: u64) {
+        self.time_us += us;
+        self.pending_us += us;
+        let dt = PHYSICS_STEP_US as f64 / 1e6;
+        while self.pending_us >= PHYSICS_STEP_US {
+            self.pending_us -= PHYSICS_STEP_US;
+            let drive = self.battery.voltage() / self.battery.nominal;
+            self.state.step(&self.params, self.board.motors.map(|speed| speed as f64 * drive), dt);
+            self.battery.current = self.state.total_thrust(&self.params) * self.battery.amps_per_newton;
+            self.battery.used += self.battery.current * dt;
+        }
+    }
+}
+
+impl DroneHal for SimHal {
+    fn set_motors(&mut self, motors: [u16; 4]) {
+        self.board.set_motors(motors);
+    }
+
+    fn get_motors(&self) -> [u16; 4] {
+        self.board.get_motors()
+    }
+
+    fn set_motor_max(&mut self, max: u16) {
+        self.board.set_motor_max(max);
+    }
+
+    fn read_dmp(&mut self) -> Quaternion {
+        self.sensors.dmp(&self.state)
+    }
+
+    fn read_raw(&mut self) -> (Accel, Gyro) {
+        self.sensors.raw(&self.state)
+    }
+
+    fn read_pressure(&mut self) -> u32 {
+        self.sensors.pressure(&self.state)
+    }
+
+    fn read_battery(&mut self) -> u16 {
+        (self.battery.voltage() * 100.0).round() as u16
+    }
+
+    fn send_bytes(&mut self, bytes: &[u8]) -> bool {
+        self.board.send_bytes(bytes)
+    }
+
+    fn receive_bytes(&mut self, buffer: &mut [u8]) -> usize {
+        self.board.receive_bytes(buffer)
+    }
+
+    fn flash_read(&mut self, address: u32, buffer: &mut [u8]) -> Result<(), FlashError> {
+        self.board.flash_read(address, buffer)
+    }
+
+    fn flash_write(&mut self, address: u32, bytes: &[u8]) -> Result<(), FlashError> {
+        self.board.flash_write(address, bytes)
+    }
+
+    fn flash_erase(&mut self) -> Result<(), FlashError> {
+        self.board.flash_erase()
+    }
+
+    fn led_on(&mut self, led: Led) {
+        self.board.led_on(led);
+    }
+
+    fn led_off(&mut self, led: Led) {
+        self.board.led_off(led);
+    }
+
+    fn led_toggle(&mut self, led: Led) {
+        self.board.led_toggle(led);
+    }
+
+    fn now_us(&self) -> u64 {
+        self.time_us
+    }
+
+    fn set_tick_frequency(&mut self, hz: u64) {
+        self.tick_us = 1_000_000 / hz;
+    }
+
+    fn wait_for_next_tick(&mut self) {
+        // the timer keeps its own pace, time spent in the tick is not added
+        let next = (self.time_us / self.tick_us + 1) * self.tick_us;
+        self.advance(next - self.time_us);
+    }
+
+    fn delay(&mut self, cycles: u32) {
+        self.advance((cycles / CYCLES_PER_US) as u64);
+    }
+}
@@ -0,0 +1,172 @@
+//! Software-in-the-loop simulation of the drone.
+//!
+//! The firmware control loop runs unchanged on a `SimHal`, whose motors drive
+//! a rigid-body model of the quadcopter and whose sensors are read back from
+//! that model. Everything is deterministic: time only moves when the
+//! simulation is stepped and the sensor noise comes from a seeded generator.
+use dronecode::control::{Controller, TICK_FREQ};
+use dronecode::control::yaw_pitch_roll::YawPitchRoll;
+use dronecode::hal::DroneHal;
+use fixed::types::I22F10;
+use share_lib::{serialize_message, Command};
+pub use crate::hal::{Battery, SimHal};
+pub use crate::physics::{Attitude, QuadParams, QuadState};
+pub use crate::sensors::{SensorNoise, Sensors};
+
+pub mod hal;
+pub mod physics;
+#[cfg(unix)]
+pub mod pty;
+pub mod sensors;
+#[cfg(target_os = "linux")]
+pub mod usb;
+
+/// The firmware flying the simulated drone
+pub struct Simulation {
+    pub controller: Controller<SimHal>,
+}
+
+impl Simulation {
+    /// # Parameters
+    ///
+    /// * `params` - The physical constants of the drone.
+    /// * `noise` - The sensor noise levels.
+    /// * `seed` - The seed of the sensor noise.
+    pub fn new(params: QuadParams, noise: SensorNoise, seed: u64) -> Self {
+        let hal = SimHal::new(params, Sensors::new(noise, seed));
+        Simulation { controller: Controller::new(hal) }
+    }
+
+    pub fn hal(&self) -> &SimHal {
+        &self.controller.drone.hal
+    }
+
+    pub fn hal_mut(&mut self) -> &mut SimHal {
+        &mut self.controller.drone.hal
+    }
+
+    pub fn state(&self) -> &QuadState {
+        &self.hal().state
+    }
+
+    /// Simulated time in seconds
+    pub fn time(&self) -> f64 {
+        self.hal().now_us() as f64 / 1e6
+    }
+
+    /// Queues a command on the uart, as the runner would send it.
+    pub fn send(&mut self, command: Command) {
+        let bytes = serialize_message(command);
+        self.hal_mut().board.rx.extend(bytes);
+    }
+
+    /// Joystick throttle at which the motors carry part of the weight of the drone
+    ///
+    /// The throttle is searched on a copy of the firmware mixer, so it includes the
+    /// thrust curve and the battery compensation of the firmware. The speed a motor
+    /// turns at follows the voltage of the simulated battery.
+    ///
+    /// # Parameters
+    ///
+    /// * `thrust` - The thrust as part of the weight, 1 hovers.
+    pub fn throttle(&self, thrust: f64) -> i16 {
+        let hal = self.hal();
+        let mut mixer = self.controller.drone.mixer.clone();
+        let drive = hal.battery.voltage() / hal.battery.nominal;
+        // thrust goes with speed^2, at 1 every motor turns at the hover speed
+        let speed = hal.params.hover_speed() * thrust.sqrt();
+        let level = YawPitchRoll::new();
+        // lift is negative and the motors speed up as the throttle goes down, bisect the joystick range
+        let (mut low, mut high) = (i16::MIN as i32, 0);
+        while high - low > 1 {
+            let mid = (low + high) / 2;
+            let motors = mixer.motors(level, I22F10::from_num(mid));
+            let mean = motors.iter().map(|&m| m as f64).sum::<f64>() / 4.0;
+            if mean * drive >= speed {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+        low as i16
+    }
+
+    /// Bytes the firmware sent since the last call
+    pub fn take_sent(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.hal_mut().board.tx)
+    }
+
+    /// Runs one iteration of the control loop and the physics up to the next tick.
+    pub fn step(&mut self) {
+        self.controller.tick();
+        self.hal_mut().wait_for_next_tick();
+    }
+
+    /// Steps the simulation for `seconds`, at `TICK_FREQ` ticks per second.
+    pub fn run(&mut self, seconds: f64) {
+        let ticks = (seconds * TICK_FREQ as f64).round() as u64;
+        for _ in 0..ticks {
+            self.step();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use share_lib::Mode;
+    use super::*;
+
+    // altitude in m and roll, pitch and yaw in degrees at every tick
+    type Trace = Vec<[f64; 4]>;
+
+    // calibrates on the ground, takes off in full control mode, stops the climb and hovers for 5s
+    fn hover(seed: u64) -> Trace {
+        let mut sim = Simulation::new(QuadParams::default(), SensorNoise::default(), seed);
+        sim.send(Command::ModeChange { mode: Mode::Calibration });
+        sim.run(0.5);
+        // the attitude gains of the scripted flight, the defaults leave them at 0
+        for (id, value) in [(0, 5), (3, 4), (5, 2), (6, 4), (8, 2)] {
+            sim.send(Command::ParamSet { id, value: I22F10::from_num(value) });
+        }
+        sim.send(Command::ModeChange { mode: Mode::FullControl });
+        sim.run(0.5);
+
+        let mut trace = Vec::new();
+        for tick in 0..700 {
+            if tick % 10 == 0 {
+                let thrust = match tick {
+                    0..100 => 1.2,
+                    100..160 => 0.8,
+                    _ => 1.0,
+                };
+                sim.send(Command::ThrottleSet { num: sim.throttle(thrust) });
+            }
+            sim.step();
+            sim.take_sent();
+            let [roll, pitch, yaw] = sim.state().attitude.to_euler();
+            trace.push([sim.state().position[2], roll.to_degrees(), pitch.to_degrees(), yaw.to_degrees()]);
+        }
+        trace
+    }
+
+    #[test]
+    fn hovers_level() {
+        let trace = hover(1);
+        let [z, ..] = trace[199];
+        assert!(z > 0.5, "did not take off, z = {z}");
+        for (tick, [z, roll, pitch, yaw]) in trace.into_iter().enumerate() {
+            assert!(z < 2.0, "climbed to {z} m at tick {tick}");
+            if tick >= 200 {
+                assert!(z > 0.5, "came down to {z} m at tick {tick}");
+            }
+            for (axis, angle) in [("roll", roll), ("pitch", pitch), ("yaw", yaw)] {
+                assert!(angle.abs() < 3.0, "{axis} of {angle} degrees at tick {tick}");
+            }
+        }
+    }
+
+    #[test]
+    fn same_seed_same_flight() {
+        assert_eq!(hover(7), hover(7));
+    }
+}
@@ -0,0 +1,73 @@
+use std::env::args;
+use fixed::types::I22F10;
+use share_lib::{Command, Mode};
+use dronecode::control::TICK_FREQ;
+use simulator::{QuadParams, SensorNoise, Simulation};
+
+// Ids of the attitude gains in the parameter registry of the firmware
+const YAW_P: u8 = 0;
+const PITCH_P: u8 = 3;
+const PITCH_D: u8 = 5;
+const ROLL_P: u8 = 6;
+const ROLL_D: u8 = 8;
+// Ticks between two throttle frames, which also keep the link alive
+const THROTTLE_PERIOD: u32 = 10;
+// Roll step flown halfway through the flight, in radians
+const ROLL_STEP: f64 = 0.1;
+// Thrust during take-off as part of the weight, and how long it lasts in ticks
+const TAKE_OFF_THRUST: f64 = 1.2;
+const TAKE_OFF_TICKS: u32 = 100;
+// Ticks the climb is stopped for afterwards, with as much less thrust. Shorter than
+// the take-off, as the drag and the lag of the motors help to stop it
+const BRAKE_TICKS: u32 = 60;
+
+/// Flies a scripted flight in full control mode and prints the state as csv.
+///
+/// The drone is calibrated on the ground, takes off, stops the climb, continues
+/// at hover throttle and answers a roll step halfway through. Usage: `simulator [seconds] [seed]`.
+fn main() {
+    let seconds: f64 = args().nth(1).and_then(|s| s.parse().ok()).unwrap_or(10.0);
+    let seed: u64 = args().nth(2).and_then(|s| s.parse().ok()).unwrap_or(1);
+    let params = QuadParams::default();
+    let mut sim = Simulation::new(params, SensorNoise::default(), seed);
+
+    sim.send(Command::ModeChange { mode: Mode::Calibration });
+    sim.run(0.5);
+    for (id, value) in [(YAW_P, 5), (PITCH_P, 4), (PITCH_D, 2), (ROLL_P, 4), (ROLL_D, 2)] {
+        sim.send(Command::ParamSet { id, value: I22F10::from_num(value) });
+    }
+    sim.send(Command::ModeChange { mode: Mode::FullControl });
+    sim.run(0.5);
+
+    let ticks = (seconds * TICK_FREQ as f64) as u32;
+    println!("time,x,y,z,roll,pitch,yaw,motor1,motor2,motor3,motor4");
+    for tick in 0..ticks {
+        if tick % THROTTLE_PERIOD == 0 {
+            let thrust = if tick < TAKE_OFF_TICKS {
+                TAKE_OFF_THRUST
+            } else if tick < TAKE_OFF_TICKS + BRAKE_TICKS {
+                2.0 - TAKE_OFF_THRUST
+            } else {
+                1.0
+            };
+            sim.send(Command::ThrottleSet { num: sim.throttle(thrust) });
+        }
+        if tick == ticks / 2 {
+            sim.send(Command::RollSet { num: I22F10::from_num(ROLL_STEP).to_bits() });
+        }
+        sim.step();
+        sim.take_sent();
+
+        let state = sim.state();
+        let [x, y, z] = state.position;
+        let [roll, pitch, yaw] = state.attitude.to_euler();
+        let [m1, m2, m3, m4] = state.motor_speed;
+        println!(
+            "{:.2},{x:.3},{y:.3},{z:.3},{:.2},{:.2},{:.2},{m1:.0},{m2:.0},{m3:.0},{m4:.0}",
+            sim.time(),
+            roll.to_degrees(),
+            pitch.to_degrees(),
+            yaw.to_degrees(),
+        );
+    }
+}
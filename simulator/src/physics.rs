@@ -0,0 +1,243 @@
+/// Standard gravity in m/s^2
+pub const GRAVITY: f64 = 9.81;
+
+pub type Vec3 = [f64; 3];
+
+fn add(a: Vec3, b: Vec3) -> Vec3 {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+fn scale(a: Vec3, k: f64) -> Vec3 {
+    [a[0] * k, a[1] * k, a[2] * k]
+}
+
+fn cross(a: Vec3, b: Vec3) -> Vec3 {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+/// Rotation from the body frame to the world frame.
+///
+/// Both frames are right-handed with z pointing up, the body x axis points to
+/// the front of the drone.
+#[derive(Clone, Copy, Debug)]
+pub struct Attitude {
+    pub w: f64,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Attitude {
+    pub const LEVEL: Attitude = Attitude { w: 1.0, x: 0.0, y: 0.0, z: 0.0 };
+
+    /// Builds an attitude from roll, pitch and yaw in radians, applied in that order.
+    pub fn from_euler(roll: f64, pitch: f64, yaw: f64) -> Self {
+        let (sr, cr) = (roll / 2.0).sin_cos();
+        let (sp, cp) = (pitch / 2.0).sin_cos();
+        let (sy, cy) = (yaw / 2.0).sin_cos();
+        Attitude {
+            w: cr * cp * cy + sr * sp * sy,
+            x: sr * cp * cy - cr * sp * sy,
+            y: cr * sp * cy + sr * cp * sy,
+            z: cr * cp * sy - sr * sp * cy,
+        }
+    }
+
+    /// Roll, pitch and yaw in radians
+    pub fn to_euler(&self) -> Vec3 {
+        let Attitude { w, x, y, z } = *self;
+        let roll = (2.0 * (w * x + y * z)).atan2(1.0 - 2.0 * (x * x + y * y));
+        let pitch = (2.0 * (w * y - z * x)).clamp(-1.0, 1.0).asin();
+        let yaw = (2.0 * (w * z + x * y)).atan2(1.0 - 2.0 * (y * y + z * z));
+        [roll, pitch, yaw]
+    }
+
+    /// Rotates a body frame vector into the world frame.
+    pub fn rotate(&self, v: Vec3) -> Vec3 {
+        let u = [self.x, self.y, self.z];
+        let t = scale(cross(u, v), 2.0);
+        add(add(v, scale(t, self.w)), cross(u, t))
+    }
+
+    /// Rotates a world frame vector into the body frame.
+    pub fn rotate_back(&self, v: Vec3) -> Vec3 {
+        Attitude { w: self.w, x: -self.x, y: -self.y, z: -self.z }.rotate(v)
+    }
+
+    /// Turns the attitude by the body rates `rates` for `dt` seconds.
+    fn integrate(&mut self, rates: Vec3, dt: f64) {
+        let Attitude { w, x, y, z } = *self;
+        let [p, q, r] = scale(rates, dt / 2.0);
+        self.w += -x * p - y * q - z * r;
+        self.x += w * p + y * r - z * q;
+        self.y += w * q - x * r + z * p;
+        self.z += w * r + x * q - y * p;
+        let norm = (self.w * self.w + self.x * self.x + self.y * self.y + self.z * self.z).sqrt();
+        self.w /= norm;
+        self.x /= norm;
+        self.y /= norm;
+        self.z /= norm;
+    }
+}
+
+/// Physical constants of the quadcopter.
+///
+/// Motor speeds are in the units `set_motors` takes at the nominal battery
+/// voltage. Motor 1 sits on the front (+x), motor 2 on the right (-y), motor 3
+/// at the back and motor 4 on the left, matching the default plus frame of
+/// `Mixer`. Motors 1 and 3 turn the frame counter-clockwise, 2 and 4 clockwise.
+#[derive(Clone, Copy, Debug)]
+pub struct QuadParams {
+    /// mass in kg
+    pub mass: f64,
+    /// distance from the centre to a motor in m
+    pub arm: f64,
+    /// moments of inertia about the body axes in kg m^2
+    pub inertia: Vec3,
+    /// thrust of a motor in N per speed^2
+    pub thrust_coeff: f64,
+    /// reaction torque of a motor in N m per speed^2
+    pub torque_coeff: f64,
+    /// time constant of a motor following its set speed in s
+    pub motor_time_constant: f64,
+    /// air drag in N per m/s
+    pub drag: f64,
+    /// rotational drag in N m per rad/s
+    pub angular_drag: f64,
+}
+
+impl Default for QuadParams {
+    fn default() -> Self {
+        QuadParams {
+            mass: 0.55,
+            arm: 0.15,
+            inertia: [5.0e-3, 5.0e-3, 9.0e-3],
+            // hovers at a motor speed of about 450
+            thrust_coeff: 6.7e-6,
+            torque_coeff: 1.3e-7,
+            motor_time_constant: 0.05,
+            drag: 0.3,
+            angular_drag: 2.0e-3,
+        }
+    }
+}
+
+impl QuadParams {
+    /// Motor speed at which the four motors carry the weight of the drone
+    pub fn hover_speed(&self) -> f64 {
+        (self.mass * GRAVITY / (4.0 * self.thrust_coeff)).sqrt()
+    }
+}
+
+// position of the motors in the body frame, in units of the arm length
+const MOTOR_POSITION: [[f64; 2]; 4] = [[1.0, 0.0], [0.0, -1.0], [-1.0, 0.0], [0.0, 1.0]];
+// direction of the reaction torque of the motors about the body z axis
+const MOTOR_SPIN: [f64; 4] = [1.0, -1.0, 1.0, -1.0];
+
+/// Rigid-body state of the quadcopter
+#[derive(Clone, Copy, Debug)]
+pub struct QuadState {
+    /// position in the world frame in m, the ground is at z = 0
+    pub position: Vec3,
+    /// velocity in the world frame in m/s
+    pub velocity: Vec3,
+    /// acceleration in the world frame in m/s^2 during the last step
+    pub acceleration: Vec3,
+    pub attitude: Attitude,
+    /// angular velocity in the body frame in rad/s
+    pub rates: Vec3,
+    /// actual speed of the motors
+    pub motor_speed: [f64; 4],
+}
+
+impl QuadState {
+    /// A drone standing level on the ground with the motors off
+    pub fn landed() -> Self {
+        QuadState {
+            position: [0.0; 3],
+            velocity: [0.0; 3],
+            acceleration: [0.0; 3],
+            attitude: Attitude::LEVEL,
+            rates: [0.0; 3],
+            motor_speed: [0.0; 4],
+        }
+    }
+
+    /// Specific force in the body frame in m/s^2, what an accelerometer measures
+    pub fn specific_force(&self) -> Vec3 {
+        self.attitude.rotate_back(add(self.acceleration, [0.0, 0.0, GRAVITY]))
+    }
+
+    /// Sum of the thrust of the four motors in N
+    pub fn total_thrust(&self, params: &QuadParams) -> f64 {
+        self.motor_speed.iter().map(|s| params.thrust_coeff * s * s).sum()
+    }
+
+    /// Advances the state by `dt` seconds.
+    ///
+    /// # Parameters
+    ///
+    /// * `params` - The physical constants of the drone.
+    /// * `motors` - The speeds the motors are driven towards.
+    /// * `dt` - The time step in seconds, about a millisecond keeps the integration stable.
+    pub fn step(&mut self, params: &QuadParams, motors: [f64; 4], dt: f64) {
+        // the motors follow their set speed with a first order lag
+        let alpha = 1.0 - (-dt / params.motor_time_constant).exp();
+        for (speed, set) in self.motor_speed.iter_mut().zip(motors) {
+            *speed += (set - *speed) * alpha;
+        }
+
+        let mut torque = [0.0; 3];
+        let mut thrust = 0.0;
+        for ((position, spin), speed) in MOTOR_POSITION.iter().zip(MOTOR_SPIN).zip(self.motor_speed) {
+            let force = params.thrust_coeff * speed * speed;
+            thrust += force;
+            torque[0] += position[1] * params.arm * force;
+            torque[1] -= position[0] * params.arm * force;
+            torque[2] += spin * params.torque_coeff * speed * speed;
+        }
+
+        // translation
+        let force = add(
+            add(self.attitude.rotate([0.0, 0.0, thrust]), [0.0, 0.0, -params.mass * GRAVITY]),
+            scale(self.velocity, -params.drag),
+        );
+        self.acceleration = scale(force, 1.0 / params.mass);
+
+        // rotation, Euler's equations
+        let inertia = params.inertia;
+        let momentum = [inertia[0] * self.rates[0], inertia[1] * self.rates[1], inertia[2] * self.rates[2]];
+        let gyroscopic = cross(self.rates, momentum);
+        let mut angular_acceleration = [0.0; 3];
+        for axis in 0..3 {
+            angular_acceleration[axis] =
+                (torque[axis] - gyroscopic[axis] - params.angular_drag * self.rates[axis]) / inertia[axis];
+        }
+
+        // the ground holds the drone up until the motors lift it
+        let grounded = self.position[2] <= 0.0 && self.acceleration[2] <= 0.0;
+        if grounded {
+            self.acceleration = [0.0; 3];
+            self.velocity = [0.0; 3];
+            self.rates = [0.0; 3];
+            self.position[2] = 0.0;
+            return;
+        }
+
+        self.velocity = add(self.velocity, scale(self.acceleration, dt));
+        self.position = add(self.position, scale(self.velocity, dt));
+        self.rates = add(self.rates, scale(angular_acceleration, dt));
+        self.attitude.integrate(self.rates, dt);
+
+        // touching down
+        if self.position[2] < 0.0 {
+            self.position[2] = 0.0;
+            self.velocity = [0.0; 3];
+            self.rates = [0.0; 3];
+        }
+    }
+}
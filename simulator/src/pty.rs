@@ -0,0 +1,81 @@
+use std::ffi::CStr;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::path::PathBuf;
+
+/// A pseudo-terminal standing in for the serial port of the drone.
+///
+/// The simulation talks to the master side, the runner opens the slave side
+/// at `path` as if it were the USB serial port. We keep the slave open
+/// ourselves so the master does not hang up while no runner is connected.
+pub struct Pty {
+    master: File,
+    _slave: File,
+    pub path: PathBuf,
+}
+
+fn check(result: libc::c_int) -> io::Result<libc::c_int> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+impl Pty {
+    /// Opens a new pseudo-terminal in raw mode with a non-blocking master.
+    pub fn open() -> io::Result<Self> {
+        // SAFETY: plain libc calls on file descriptors we own, the name buffer
+        // is nul terminated by ptsname_r on success
+        unsafe {
+            let fd = check(libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY))?;
+            let master = File::from_raw_fd(fd);
+            check(libc::grantpt(fd))?;
+            check(libc::unlockpt(fd))?;
+            let mut name = [0 as libc::c_char; 128];
+            let error = libc::ptsname_r(fd, name.as_mut_ptr(), name.len());
+            if error != 0 {
+                return Err(io::Error::from_raw_os_error(error));
+            }
+            let path = PathBuf::from(CStr::from_ptr(name.as_ptr()).to_str().unwrap());
+
+            let slave = File::options().read(true).write(true).open(&path)?;
+            let mut termios = std::mem::zeroed::<libc::termios>();
+            check(libc::tcgetattr(slave.as_raw_fd(), &mut termios))?;
+            libc::cfmakeraw(&mut termios);
+            check(libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios))?;
+
+            let flags = check(libc::fcntl(fd, libc::F_GETFL))?;
+            check(libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK))?;
+            Ok(Pty { master, _slave: slave, path })
+        }
+    }
+
+    /// Reads what the runner wrote, without blocking.
+    ///
+    /// # Returns
+    ///
+    /// Returns the bytes that were waiting, possibly none.
+    pub fn read_available(&mut self) -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        let mut buffer = [0; 256];
+        loop {
+            match self.master.read(&mut buffer) {
+                Ok(0) => return Ok(bytes),
+                Ok(n) => bytes.extend_from_slice(&buffer[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(bytes),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Writes bytes for the runner. Bytes that do not fit in the terminal
+    /// buffer are dropped, like a uart nobody reads.
+    pub fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        match self.master.write_all(bytes) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(()),
+            result => result,
+        }
+    }
+}
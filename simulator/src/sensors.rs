@@ -0,0 +1,109 @@
+use dronecode::hal::{Accel, Gyro, Quaternion};
+use crate::physics::{QuadState, GRAVITY};
+
+// scale of the raw MPU readings, +-2g and +-2000 deg/s full range
+const ACCEL_LSB_PER_G: f64 = 16384.0;
+const GYRO_LSB_PER_DPS: f64 = 16.4;
+// air pressure at the ground in Pa
+const GROUND_PRESSURE: f64 = 101325.0;
+
+/// Small deterministic random number generator (xorshift64*), so a simulation
+/// with the same seed always flies the same way.
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // the state must never be zero
+        Rng { state: seed ^ 0x9E37_79B9_7F4A_7C15 | 1 }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Uniform in (0, 1]
+    fn uniform(&mut self) -> f64 {
+        ((self.next_u64() >> 11) + 1) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Normally distributed with mean 0 and standard deviation `sigma` (Box-Muller)
+    pub fn gaussian(&mut self, sigma: f64) -> f64 {
+        let r = (-2.0 * self.uniform().ln()).sqrt();
+        let phi = 2.0 * std::f64::consts::PI * self.uniform();
+        sigma * r * phi.cos()
+    }
+}
+
+/// Noise levels of the sensors
+#[derive(Clone, Copy, Debug)]
+pub struct SensorNoise {
+    /// barometer noise in Pa
+    pub pressure: f64,
+    /// accelerometer noise in m/s^2
+    pub accel: f64,
+    /// gyroscope noise in rad/s
+    pub gyro: f64,
+}
+
+impl Default for SensorNoise {
+    fn default() -> Self {
+        SensorNoise {
+            pressure: 3.0,
+            accel: 0.05,
+            gyro: 0.005,
+        }
+    }
+}
+
+/// Turns the simulated state into the readings the firmware gets from the
+/// MPU and the barometer.
+///
+/// The raw gyro y and z axes of the board point the other way than its
+/// accelerometer and motion processor, so a reading is mirrored on those axes.
+pub struct Sensors {
+    pub noise: SensorNoise,
+    rng: Rng,
+}
+
+impl Sensors {
+    pub fn new(noise: SensorNoise, seed: u64) -> Self {
+        Sensors { noise, rng: Rng::new(seed) }
+    }
+
+    /// The attitude quaternion of the motion processor, which filters out the noise
+    pub fn dmp(&self, state: &QuadState) -> Quaternion {
+        let q = state.attitude;
+        Quaternion { w: q.w as f32, x: q.x as f32, y: q.y as f32, z: q.z as f32 }
+    }
+
+    /// Raw accelerometer and gyroscope readings
+    pub fn raw(&mut self, state: &QuadState) -> (Accel, Gyro) {
+        let force = state.specific_force();
+        let accel = force.map(|f| {
+            let f = f + self.rng.gaussian(self.noise.accel);
+            (f / GRAVITY * ACCEL_LSB_PER_G).round().clamp(i16::MIN as f64, i16::MAX as f64) as i16
+        });
+        let rates = [state.rates[0], -state.rates[1], -state.rates[2]];
+        let gyro = rates.map(|r| {
+            let r = r + self.rng.gaussian(self.noise.gyro);
+            (r.to_degrees() * GYRO_LSB_PER_DPS).round().clamp(i16::MIN as f64, i16::MAX as f64) as i16
+        });
+        (
+            Accel { x: accel[0], y: accel[1], z: accel[2] },
+            Gyro { x: gyro[0], y: gyro[1], z: gyro[2] },
+        )
+    }
+
+    /// Barometer reading in Pa, from the international standard atmosphere
+    pub fn pressure(&mut self, state: &QuadState) -> u32 {
+        let height = state.position[2];
+        let pressure = GROUND_PRESSURE * (1.0 - 2.25577e-5 * height).powf(5.25588);
+        (pressure + self.rng.gaussian(self.noise.pressure)).round() as u32
+    }
+}
@@ -0,0 +1,136 @@
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+
+/// Vendor and product id of the FTDI chip on the drone, the runner looks for these.
+const VENDOR_ID: &str = "0403";
+const PRODUCT_ID: &str = "6015";
+/// Name of the serial port the runner sees.
+const PORT: &str = "ttyUSB0";
+
+/// Makes a pseudo-terminal look like the drone on USB to the processes it starts.
+///
+/// The runner finds the drone by the USB ids of its serial chip, which it
+/// reads from sysfs. A process started with [`DronePort::spawn`] gets its own
+/// user and mount namespace in which `/sys/class/tty` only holds a USB serial
+/// port with those ids, and `/dev/ttyUSB0` leads to the pseudo-terminal. The
+/// rest of `/dev` is still there, so the process runs as it normally would.
+pub struct DronePort {
+    dir: PathBuf,
+    pty: PathBuf,
+}
+
+fn c_path(path: &Path) -> CString {
+    CString::new(path.as_os_str().as_bytes()).expect("paths have no nul bytes")
+}
+
+fn check(result: libc::c_int) -> io::Result<()> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Writes a file in /proc/self, without allocating, as it is called between fork and exec.
+fn write_proc(path: &CString, contents: &CString) -> io::Result<()> {
+    // SAFETY: plain libc calls on nul terminated strings and a file descriptor we own
+    unsafe {
+        let fd = libc::open(path.as_ptr(), libc::O_WRONLY);
+        check(fd)?;
+        let bytes = contents.as_bytes();
+        let written = libc::write(fd, bytes.as_ptr().cast(), bytes.len());
+        libc::close(fd);
+        if written < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+fn mount(source: &CString, target: &CString, fstype: Option<&CString>, flags: libc::c_ulong) -> io::Result<()> {
+    let fstype = fstype.map_or(std::ptr::null(), |fstype| fstype.as_ptr());
+    // SAFETY: the strings are nul terminated and outlive the call
+    check(unsafe { libc::mount(source.as_ptr(), target.as_ptr(), fstype, flags, std::ptr::null()) })
+}
+
+impl DronePort {
+    /// Builds the USB serial port in sysfs for the pseudo-terminal at `pty`.
+    /// It is only visible to the processes started with [`DronePort::spawn`].
+    pub fn new(pty: &Path) -> io::Result<Self> {
+        let dir = std::env::temp_dir().join(format!("drone-sim-{}", std::process::id()));
+        let device = dir.join("tty").join(PORT).join("device");
+        fs::create_dir_all(device.join("driver"))?;
+        fs::write(device.join("idVendor"), VENDOR_ID)?;
+        fs::write(device.join("idProduct"), PRODUCT_ID)?;
+        fs::write(device.join("manufacturer"), "FTDI")?;
+        fs::write(device.join("product"), "simulated drone")?;
+        fs::write(dir.join("drivers"), "usbserial            /dev/ttyUSB   188 0-511 serial\n")?;
+        fs::create_dir_all(dir.join("dev"))?;
+        Ok(DronePort { dir, pty: pty.to_path_buf() })
+    }
+
+    /// Starts `command` with the pseudo-terminal as the drone on `/dev/ttyUSB0`.
+    ///
+    /// # Returns
+    ///
+    /// Returns the started process, or an error when the namespaces are not
+    /// allowed, e.g. when unprivileged user namespaces are turned off.
+    pub fn spawn(&self, command: &mut Command) -> io::Result<Child> {
+        // everything the child needs is prepared here, it may not allocate after the fork
+        let old_dev = self.dir.join("dev");
+        let links: Vec<(CString, CString)> = fs::read_dir("/dev")?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| (c_path(&old_dev.join(entry.file_name())), c_path(&Path::new("/dev").join(entry.file_name()))))
+            .chain([(c_path(&self.pty), c_path(&Path::new("/dev").join(PORT)))])
+            .collect();
+        let old_dev = c_path(&old_dev);
+        let tty = c_path(&self.dir.join("tty"));
+        let drivers = c_path(&self.dir.join("drivers"));
+        let dev = c_path(Path::new("/dev"));
+        let sys_tty = c_path(Path::new("/sys/class/tty"));
+        let proc_drivers = c_path(Path::new("/proc/tty/drivers"));
+        let root = c_path(Path::new("/"));
+        let tmpfs = c_path(Path::new("tmpfs"));
+        // the process keeps its own user and group inside the namespace
+        // SAFETY: getuid and getgid cannot fail
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        let uid_map = CString::new(format!("{uid} {uid} 1")).unwrap();
+        let gid_map = CString::new(format!("{gid} {gid} 1")).unwrap();
+        let deny = CString::new("deny").unwrap();
+        let proc_files = ["/proc/self/uid_map", "/proc/self/setgroups", "/proc/self/gid_map"]
+            .map(|path| CString::new(path).unwrap());
+
+        // SAFETY: the closure only makes system calls on the strings prepared above
+        unsafe {
+            command.pre_exec(move || {
+                check(libc::unshare(libc::CLONE_NEWUSER | libc::CLONE_NEWNS))?;
+                write_proc(&proc_files[0], &uid_map)?;
+                write_proc(&proc_files[1], &deny)?;
+                write_proc(&proc_files[2], &gid_map)?;
+                // nothing mounted here is seen outside the namespace
+                mount(&root, &root, None, libc::MS_REC | libc::MS_PRIVATE)?;
+                // /dev is replaced by links to the old one, next to the drone port
+                mount(&dev, &old_dev, None, libc::MS_BIND | libc::MS_REC)?;
+                mount(&tmpfs, &dev, Some(&tmpfs), 0)?;
+                for (target, link) in &links {
+                    check(libc::symlink(target.as_ptr(), link.as_ptr()))?;
+                }
+                mount(&tty, &sys_tty, None, libc::MS_BIND)?;
+                mount(&drivers, &proc_drivers, None, libc::MS_BIND)?;
+                Ok(())
+            })
+        };
+        command.spawn()
+    }
+}
+
+impl Drop for DronePort {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}